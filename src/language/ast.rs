@@ -0,0 +1,597 @@
+//! Syntax tree types produced by `parser::Parser`.
+//!
+//! ```
+//! use pydb::language::ast::{dump, Expression, Identifier, Literal, Operator, Program, Statement};
+//!
+//! // x + 1
+//! let x = Expression::Identifier(Identifier {
+//!     name: "x".to_string(),
+//! });
+//! let one = Expression::Literal(Literal::Int(1));
+//! let sum = Expression::BinaryExpression(
+//!     Box::new(Statement::ExpressionStatement(x)),
+//!     Operator::Add,
+//!     Box::new(Statement::ExpressionStatement(one)),
+//! );
+//!
+//! let program = Program::new(vec![Statement::ExpressionStatement(sum)]);
+//! assert_eq!(dump(&program), "(+ x 1)");
+//! ```
+
+use std::fmt;
+
+#[derive(Debug)]
+pub struct Program {
+    pub body: Vec<CommentedStatement>,
+    /// Comments after the last statement, which belong to no statement.
+    pub trailing_comments: Vec<String>,
+}
+
+/// A statement of a program or block together with the comments around it.
+#[derive(Debug)]
+pub struct CommentedStatement {
+    pub statement: Statement,
+    /// Only filled in when the parser is asked to preserve comments.
+    pub trivia: Trivia,
+}
+
+/// Comments surrounding a statement: the comment lines directly above it
+/// and a comment at the end of its line.
+#[derive(Debug, Default)]
+pub struct Trivia {
+    pub leading: Vec<String>,
+    pub trailing: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Statement {
+    ExpressionStatement(Expression),
+    RaiseStatement {
+        exception: Option<Expression>,
+        cause: Option<Expression>,
+    },
+    MatchStatement {
+        subject: Expression,
+        cases: Vec<MatchCase>,
+    },
+}
+
+/// One `case pattern:` clause of a `match` statement and its block.
+#[derive(Debug)]
+pub struct MatchCase {
+    pub pattern: Pattern,
+    /// Comments above the `case` line and at the end of it lead the first
+    /// statement of the body.
+    pub body: Vec<CommentedStatement>,
+}
+
+#[derive(Debug)]
+pub enum Pattern {
+    Literal(Literal),
+    /// A bare name, which binds the subject to that name.
+    Capture(Identifier),
+    /// `_`, which matches anything without binding it.
+    Wildcard,
+    /// `[a, b]`, `(a, b)` or `a, b`.
+    Sequence(Vec<Pattern>),
+}
+
+#[derive(Debug)]
+pub enum Expression {
+    Literal(Literal),
+    Identifier(Identifier),
+    UnaryExpression(Box<Expression>, Operator),
+    BinaryExpression(Box<Statement>, Operator, Box<Statement>),
+    MemberExpression(Box<Statement>, Identifier),
+    FunctionCallExpression(Box<Statement>, Vec<Statement>),
+    SubscriptExpression(Box<Statement>, Box<Statement>),
+    LogicalExpression {
+        op: Operator,
+        left: Box<Statement>,
+        right: Box<Statement>,
+    },
+    /// Two or more chained comparisons such as `a < b in c`, meaning
+    /// `a < b and b in c` with `b` evaluated once. A single comparison is a
+    /// plain `BinaryExpression`.
+    ComparisonChain {
+        left: Box<Statement>,
+        comparisons: Vec<(Operator, Statement)>,
+    },
+    GeneratorExpression {
+        element: Box<Statement>,
+        target: Identifier,
+        iter: Box<Statement>,
+        conditions: Vec<Statement>,
+    },
+}
+#[derive(Debug)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulus,
+    Exponent,
+    MatMul,
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessThanOrEqual,
+    GreaterThanOrEqual,
+    Is,
+    IsNot,
+    In,
+    NotIn,
+    Not,
+}
+
+#[derive(Debug)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    None,
+}
+
+#[derive(Debug)]
+pub struct Identifier {
+    pub name: String,
+}
+
+/// The operator as written in source, e.g. `>=` or `not in`.
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Modulus => "%",
+            Operator::Exponent => "**",
+            Operator::MatMul => "@",
+            Operator::And => "and",
+            Operator::Or => "or",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::LessThan => "<",
+            Operator::GreaterThan => ">",
+            Operator::LessThanOrEqual => "<=",
+            Operator::GreaterThanOrEqual => ">=",
+            Operator::Is => "is",
+            Operator::IsNot => "is not",
+            Operator::In => "in",
+            Operator::NotIn => "not in",
+            Operator::Not => "not",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl From<Statement> for CommentedStatement {
+    fn from(statement: Statement) -> Self {
+        CommentedStatement {
+            statement,
+            trivia: Trivia::default(),
+        }
+    }
+}
+
+impl Program {
+    /// A program of `body` without any comments.
+    pub fn new(body: Vec<Statement>) -> Self {
+        Program {
+            body: body.into_iter().map(CommentedStatement::from).collect(),
+            trailing_comments: Vec::new(),
+        }
+    }
+
+    /// Returns the module docstring, i.e. a string literal appearing as the
+    /// first statement of the program.
+    pub fn docstring(&self) -> Option<&str> {
+        match self.body.first().map(|item| &item.statement) {
+            Some(Statement::ExpressionStatement(Expression::Literal(Literal::String(v)))) => {
+                Some(v.as_str())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Rebuilds the tree node by node, for tools that rewrite code. Every method
+/// defaults to recursing into the children unchanged, so an implementation
+/// only overrides the nodes it cares about.
+pub trait Transform {
+    fn transform_program(&mut self, program: Program) -> Program {
+        Program {
+            body: walk_body(self, program.body),
+            trailing_comments: program.trailing_comments,
+        }
+    }
+
+    fn transform_statement(&mut self, statement: Statement) -> Statement {
+        walk_statement(self, statement)
+    }
+
+    fn transform_expression(&mut self, expression: Expression) -> Expression {
+        walk_expression(self, expression)
+    }
+
+    fn transform_pattern(&mut self, pattern: Pattern) -> Pattern {
+        walk_pattern(self, pattern)
+    }
+
+    /// Called for every variable name, both where it is used and where a
+    /// generator or case pattern binds it. Attribute names are not variables
+    /// and skip this.
+    fn transform_identifier(&mut self, identifier: Identifier) -> Identifier {
+        identifier
+    }
+}
+
+/// Default `Transform` recursion for a statement.
+pub fn walk_statement<T: Transform + ?Sized>(transform: &mut T, statement: Statement) -> Statement {
+    match statement {
+        Statement::ExpressionStatement(expression) => {
+            Statement::ExpressionStatement(transform.transform_expression(expression))
+        }
+        Statement::RaiseStatement { exception, cause } => Statement::RaiseStatement {
+            exception: exception.map(|v| transform.transform_expression(v)),
+            cause: cause.map(|v| transform.transform_expression(v)),
+        },
+        Statement::MatchStatement { subject, cases } => Statement::MatchStatement {
+            subject: transform.transform_expression(subject),
+            cases: cases
+                .into_iter()
+                .map(|case| MatchCase {
+                    pattern: transform.transform_pattern(case.pattern),
+                    body: walk_body(transform, case.body),
+                })
+                .collect(),
+        },
+    }
+}
+
+/// Transforms each statement of a program or block, keeping its comments.
+fn walk_body<T: Transform + ?Sized>(
+    transform: &mut T,
+    body: Vec<CommentedStatement>,
+) -> Vec<CommentedStatement> {
+    body.into_iter()
+        .map(|item| CommentedStatement {
+            statement: transform.transform_statement(item.statement),
+            trivia: item.trivia,
+        })
+        .collect()
+}
+
+/// Default `Transform` recursion for a case pattern.
+pub fn walk_pattern<T: Transform + ?Sized>(transform: &mut T, pattern: Pattern) -> Pattern {
+    match pattern {
+        Pattern::Literal(_) | Pattern::Wildcard => pattern,
+        Pattern::Capture(identifier) => {
+            Pattern::Capture(transform.transform_identifier(identifier))
+        }
+        Pattern::Sequence(patterns) => Pattern::Sequence(
+            patterns
+                .into_iter()
+                .map(|pattern| transform.transform_pattern(pattern))
+                .collect(),
+        ),
+    }
+}
+
+/// Default `Transform` recursion for an expression.
+pub fn walk_expression<T: Transform + ?Sized>(
+    transform: &mut T,
+    expression: Expression,
+) -> Expression {
+    let mut boxed = |statement: Box<Statement>| Box::new(transform.transform_statement(*statement));
+
+    match expression {
+        Expression::Literal(_) => expression,
+        Expression::Identifier(identifier) => {
+            Expression::Identifier(transform.transform_identifier(identifier))
+        }
+        Expression::UnaryExpression(operand, op) => {
+            Expression::UnaryExpression(Box::new(transform.transform_expression(*operand)), op)
+        }
+        Expression::BinaryExpression(left, op, right) => {
+            Expression::BinaryExpression(boxed(left), op, boxed(right))
+        }
+        Expression::LogicalExpression { op, left, right } => Expression::LogicalExpression {
+            op,
+            left: boxed(left),
+            right: boxed(right),
+        },
+        Expression::ComparisonChain { left, comparisons } => {
+            let left = boxed(left);
+            Expression::ComparisonChain {
+                left,
+                comparisons: comparisons
+                    .into_iter()
+                    .map(|(op, right)| (op, transform.transform_statement(right)))
+                    .collect(),
+            }
+        }
+        Expression::MemberExpression(object, property) => {
+            Expression::MemberExpression(boxed(object), property)
+        }
+        Expression::FunctionCallExpression(callee, arguments) => {
+            let callee = boxed(callee);
+            Expression::FunctionCallExpression(
+                callee,
+                arguments
+                    .into_iter()
+                    .map(|argument| transform.transform_statement(argument))
+                    .collect(),
+            )
+        }
+        Expression::SubscriptExpression(object, index) => {
+            Expression::SubscriptExpression(boxed(object), boxed(index))
+        }
+        Expression::GeneratorExpression {
+            element,
+            target,
+            iter,
+            conditions,
+        } => {
+            let element = boxed(element);
+            let iter = boxed(iter);
+            Expression::GeneratorExpression {
+                element,
+                target: transform.transform_identifier(target),
+                iter,
+                conditions: conditions
+                    .into_iter()
+                    .map(|condition| transform.transform_statement(condition))
+                    .collect(),
+            }
+        }
+    }
+}
+
+/// Renames a variable wherever it is used or bound. Attribute names such as
+/// the `x` in `obj.x` are left alone since they are not variables.
+pub struct RenameIdentifier {
+    pub from: String,
+    pub to: String,
+}
+
+impl Transform for RenameIdentifier {
+    fn transform_identifier(&mut self, identifier: Identifier) -> Identifier {
+        if identifier.name == self.from {
+            Identifier {
+                name: self.to.clone(),
+            }
+        } else {
+            identifier
+        }
+    }
+}
+
+/// Renders a program as S-expressions, one top-level statement per line,
+/// e.g. `(+ x (* 2 y))`. Handier than `{:#?}` when reading large trees.
+pub fn dump(program: &Program) -> String {
+    program
+        .body
+        .iter()
+        .map(|item| dump_statement(&item.statement))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn dump_statement(statement: &Statement) -> String {
+    match statement {
+        Statement::ExpressionStatement(expression) => dump_expression(expression),
+        Statement::RaiseStatement { exception, cause } => {
+            let mut out = String::from("(raise");
+            if let Some(exception) = exception {
+                out.push(' ');
+                out.push_str(&dump_expression(exception));
+            }
+            if let Some(cause) = cause {
+                out.push_str(&format!(" (from {})", dump_expression(cause)));
+            }
+            out.push(')');
+            out
+        }
+        Statement::MatchStatement { subject, cases } => {
+            let mut out = format!("(match {}", dump_expression(subject));
+            for case in cases {
+                out.push_str(&format!(" (case {}", dump_pattern(&case.pattern)));
+                for item in &case.body {
+                    out.push(' ');
+                    out.push_str(&dump_statement(&item.statement));
+                }
+                out.push(')');
+            }
+            out.push(')');
+            out
+        }
+    }
+}
+
+fn dump_pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Literal(literal) => dump_literal(literal),
+        Pattern::Capture(identifier) => identifier.name.clone(),
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Sequence(patterns) => format!(
+            "[{}]",
+            patterns
+                .iter()
+                .map(dump_pattern)
+                .collect::<Vec<String>>()
+                .join(" ")
+        ),
+    }
+}
+
+fn dump_literal(literal: &Literal) -> String {
+    match literal {
+        Literal::Int(v) => v.to_string(),
+        Literal::Float(v) => format!("{:?}", v),
+        Literal::String(v) => format!("{:?}", v),
+        Literal::Bool(true) => "True".to_string(),
+        Literal::Bool(false) => "False".to_string(),
+        Literal::None => "None".to_string(),
+    }
+}
+
+fn dump_expression(expression: &Expression) -> String {
+    match expression {
+        Expression::Literal(literal) => dump_literal(literal),
+        Expression::Identifier(identifier) => identifier.name.clone(),
+        Expression::UnaryExpression(operand, op) => {
+            format!("({} {})", op, dump_expression(operand))
+        }
+        Expression::BinaryExpression(left, op, right) => format!(
+            "({} {} {})",
+            op,
+            dump_statement(left),
+            dump_statement(right)
+        ),
+        Expression::LogicalExpression { op, left, right } => format!(
+            "({} {} {})",
+            op,
+            dump_statement(left),
+            dump_statement(right)
+        ),
+        Expression::ComparisonChain { left, comparisons } => {
+            let mut out = format!("(compare {}", dump_statement(left));
+            for (op, right) in comparisons {
+                out.push_str(&format!(" {} {}", op, dump_statement(right)));
+            }
+            out.push(')');
+            out
+        }
+        Expression::MemberExpression(object, property) => {
+            format!("(. {} {})", dump_statement(object), property.name)
+        }
+        Expression::FunctionCallExpression(callee, arguments) => {
+            let mut out = format!("(call {}", dump_statement(callee));
+            for argument in arguments {
+                out.push(' ');
+                out.push_str(&dump_statement(argument));
+            }
+            out.push(')');
+            out
+        }
+        Expression::SubscriptExpression(object, index) => format!(
+            "(subscript {} {})",
+            dump_statement(object),
+            dump_statement(index)
+        ),
+        Expression::GeneratorExpression {
+            element,
+            target,
+            iter,
+            conditions,
+        } => {
+            let mut out = format!(
+                "(generator {} (for {} {})",
+                dump_statement(element),
+                target.name,
+                dump_statement(iter)
+            );
+            for condition in conditions {
+                out.push_str(&format!(" (if {})", dump_statement(condition)));
+            }
+            out.push(')');
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::parser::Parser;
+    use crate::language::tokenizer::tokenize;
+
+    fn parse_source(source: &str) -> Program {
+        Parser::new(&tokenize(source).unwrap()).parse().unwrap()
+    }
+
+    fn rename(source: &str, from: &str, to: &str) -> String {
+        let mut rename = RenameIdentifier {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        dump(&rename.transform_program(parse_source(source)))
+    }
+
+    #[test]
+    fn rename_covers_generator_targets() {
+        assert_eq!(
+            rename("(x for x in xs if x)\n", "x", "y"),
+            "(generator y (for y xs) (if y))"
+        );
+    }
+
+    #[test]
+    fn rename_covers_case_captures() {
+        assert_eq!(
+            rename("match p:\n    case [x, _]:\n        x\n", "x", "y"),
+            "(match p (case [y _] y))"
+        );
+    }
+
+    #[test]
+    fn transform_keeps_comments_with_their_statements() {
+        let tokens = tokenize("# about x\nx\nf(x)  # call\n").unwrap();
+        let program = Parser::new(&tokens).with_comments(true).parse().unwrap();
+        let mut rename = RenameIdentifier {
+            from: "x".to_string(),
+            to: "y".to_string(),
+        };
+
+        let program = rename.transform_program(program);
+        assert_eq!(dump(&program), "y\n(call f y)");
+        assert_eq!(program.body[0].trivia.leading, vec![" about x"]);
+        assert_eq!(program.body[1].trivia.trailing, vec![" call"]);
+    }
+
+    #[test]
+    fn operators_display_as_source_symbols() {
+        assert_eq!(format!("{}", Operator::Exponent), "**");
+        assert_eq!(format!("{}", Operator::GreaterThanOrEqual), ">=");
+        assert_eq!(format!("{}", Operator::And), "and");
+        assert_eq!(format!("{}", Operator::IsNot), "is not");
+        assert_eq!(format!("{}", Operator::NotIn), "not in");
+    }
+
+    #[test]
+    fn dump_snapshot() {
+        let source = "\
+print(-x + 2 * y, obj.items[0], 'text', 1.5, None)
+a and not b or c in d
+sum(v for v in values if v is not None)
+raise ValueError(\"bad\") from err
+match point:
+    case [0, y]:
+        y
+";
+        assert_eq!(
+            dump(&parse_source(source)),
+            "\
+(call print (+ (- x) (* 2 y)) (subscript (. obj items) 0) \"text\" 1.5 None)
+(or (and a (not b)) (in c d))
+(call sum (generator v (for v values) (if (is not v None))))
+(raise (call ValueError \"bad\") (from err))
+(match point (case [0 y] y))"
+        );
+    }
+
+    #[test]
+    fn rename_identifier_throughout_a_program() {
+        assert_eq!(
+            rename("x\nf(x, x.x)[x] + y\nraise x from E(x)\n", "x", "y"),
+            "y\n(+ (subscript (call f y (. y x)) y) y)\n(raise y (from (call E y)))"
+        );
+    }
+}
@@ -1,163 +1,1122 @@
-use super::tokenizer::Token;
+use super::ast::{
+    CommentedStatement, Expression, Identifier, Literal, MatchCase, Operator, Pattern, Program,
+    Statement, Trivia,
+};
+use super::tokenizer::{tokenize, Token, TokenKind, TokenizeError, Tokenizer};
+use std::fmt;
+
+/// Default limit on how deeply expressions may nest before parsing gives
+/// up, well before recursive descent would overflow the stack. Every level
+/// costs around ten parser frames, so this stays low enough for a 2 MB
+/// thread stack in a debug build.
+const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// Limit on the operators and postfix operations chained in one statement,
+/// as in `a + b + c` or `f()()`. Chaining costs the parser no stack, but
+/// each link makes the tree one level deeper, and dropping, dumping or
+/// transforming the tree recurses through every level.
+const MAX_CHAINED: usize = 256;
+
+static EOF_TOKEN: Token = Token::EOF;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current_token: usize,
+    depth: usize,
+    max_depth: usize,
+    // Brackets open in the statement being parsed
+    brackets: usize,
+    // Operations chained so far in the statement being parsed
+    chained: usize,
+    preserve_comments: bool,
+    // Comment lines waiting for the statement they lead
+    pending_comments: Vec<String>,
+    // The comment ending the line of the statement being parsed
+    line_comments: Vec<String>,
 }
 
 #[derive(Debug)]
-pub struct Program {
-    body: Vec<Statement>,
-}
-#[derive(Debug)]
-enum Statement {
-    ExpressionStatement(Expression),
+pub struct ParseError {
+    pub message: String,
+    // The tokenizer error this one was made from, if any
+    cause: Option<TokenizeError>,
 }
-#[derive(Debug)]
-enum Expression {
-    Literal(Literal),
-    Identifier(Identifier),
-    UnaryExpression(Box<Expression>, Operator),
-    BinaryExpression(Box<Statement>, Operator, Box<Statement>),
+
+impl ParseError {
+    fn new(message: String) -> Self {
+        ParseError {
+            message,
+            cause: None,
+        }
+    }
 }
-#[derive(Debug)]
-enum Operator {
-    Add,
-    Subtract,
-    Multiply,
-    Divide,
-    Modulus,
-    Exponent
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
-#[derive(Debug)]
-enum Literal {
-    Int(i64),
-    Float(f64),
-    String(String),
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.cause
+            .as_ref()
+            .map(|err| err as &(dyn std::error::Error + 'static))
+    }
 }
 
+/// What `Parser::parse_interactive` made of the input typed so far.
 #[derive(Debug)]
-struct Identifier {
-    name: String,
+pub enum InteractiveInput {
+    /// The input holds whole statements only.
+    Complete(Program),
+    /// The input stops partway through a statement, so a REPL should read
+    /// another line and try again with it appended.
+    Incomplete,
 }
 
-impl Program {
-    fn new(body: Vec<Statement>) -> Self {
-        Program { body }
+impl From<TokenizeError> for ParseError {
+    fn from(err: TokenizeError) -> Self {
+        ParseError {
+            message: err.message.clone(),
+            cause: Some(err),
+        }
     }
 }
 
 impl Parser {
-    pub fn new(tokens: &Vec<Token>) -> Self {
+    pub fn new(tokens: &[Token]) -> Self {
         Parser {
             tokens: tokens.to_vec(),
             current_token: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            brackets: 0,
+            chained: 0,
+            preserve_comments: false,
+            pending_comments: Vec::new(),
+            line_comments: Vec::new(),
         }
     }
 
+    /// Keep comments as trivia on the statements they belong to instead of
+    /// discarding them. Useful for formatters and documentation tools.
+    pub fn with_comments(mut self, preserve_comments: bool) -> Self {
+        self.preserve_comments = preserve_comments;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
     fn not_eof(&mut self) -> bool {
         let current_token = self.tokens.get(self.current_token);
-        let mut is_eof = false;
-        if let Some(v) = current_token {
-            is_eof = match v {
-                Token::EOF => true,
-                _ => false,
-            };
-        }
-
-        !is_eof
+        !matches!(current_token, None | Some(Token::EOF))
     }
 
+    // Running past the end of the tokens reads as EOF, so a token list
+    // without a trailing EOF (or an empty one) can't make the parser panic
     fn get_current_token(&mut self) -> &Token {
-        self.tokens.get(self.current_token).unwrap()
+        self.skip_bracketed_comments();
+        self.tokens.get(self.current_token).unwrap_or(&EOF_TOKEN)
     }
 
     fn advance(&mut self) -> &Token {
+        self.skip_bracketed_comments();
         let current_token = self.tokens.get(self.current_token);
-        self.current_token += 1;
-        current_token.unwrap()
+        match current_token {
+            Some(Token::LParen | Token::LBracket | Token::LBrace) => self.brackets += 1,
+            Some(Token::RParen | Token::RBracket | Token::RBrace) => {
+                self.brackets = self.brackets.saturating_sub(1)
+            }
+            _ => {}
+        }
+        if current_token.is_some() {
+            self.current_token += 1;
+        }
+        current_token.unwrap_or(&EOF_TOKEN)
+    }
+
+    /// Steps over comments on the lines a bracketed expression spans, which
+    /// would otherwise land in the middle of it. When comments are preserved
+    /// they trail the statement, like a comment at the end of its line.
+    fn skip_bracketed_comments(&mut self) {
+        while self.brackets > 0 {
+            let Some(Token::Comment(comment)) = self.tokens.get(self.current_token) else {
+                break;
+            };
+            if self.preserve_comments {
+                self.line_comments.push(comment.clone());
+            }
+            self.current_token += 1;
+        }
     }
 
-    fn expect(&mut self, expected: Token, error_message: String) {
-        let current_token = self.tokens.get(self.current_token).unwrap();
-        self.current_token += 1;
+    /// Consumes the current token, failing with `error_message` unless it is
+    /// of the `expected` kind. Only the kind is compared, so any identifier,
+    /// integer, etc. matches. Returns the consumed token.
+    fn expect(&mut self, expected: TokenKind, error_message: String) -> Result<Token, ParseError> {
+        let current_token = self.advance().clone();
 
-        if *current_token != expected {
-            panic!("{}", error_message.to_string());
+        if current_token.kind() != expected {
+            return Err(ParseError::new(error_message));
+        }
+
+        Ok(current_token)
+    }
+
+    /// Consumes an identifier and returns it, or fails with a message saying
+    /// where the name was expected, e.g. "after '.'".
+    fn expect_identifier(&mut self, context: &str) -> Result<Identifier, ParseError> {
+        match self.advance() {
+            Token::Identifier(v) => Ok(Identifier {
+                name: v.to_string(),
+            }),
+            token => Err(ParseError::new(format!(
+                "Error: expected a name {}, found {}",
+                context,
+                describe(token)
+            ))),
         }
     }
 
     /**
-     * Order of precedence
-     * 
-     * Assignment
-     * MemberExpression
-     * FunctionCallExpression
-     * LogicalExpression
-     * ComparisionExpression
-     * AdditiveExpression
-     * MultiplicativeExpression
-     * UnaryExpression
+     * Order of precedence, loosest first
+     *
+     * LogicalExpression (or)
+     * LogicalExpression (and)
+     * UnaryExpression (not)
+     * ComparisonExpression (== != < > <= >= is is not in not in)
+     * AdditiveExpression (+ -)
+     * MultiplicativeExpression (* / % @)
+     * UnaryExpression (- +)
+     * PowerExpression (**)
+     * PostfixExpression (member access, call, subscript)
      * PrimaryExpression
      */
-    pub fn parse(&mut self) -> Program {
+    pub fn parse(&mut self) -> Result<Program, ParseError> {
+        validate_brackets(self.remaining())?;
+
+        let mut body = Vec::new();
+        while let Some(statement) = self.parse_next_statement()? {
+            body.push(statement);
+        }
+
+        Ok(Program {
+            body,
+            trailing_comments: std::mem::take(&mut self.pending_comments),
+        })
+    }
+
+    /// Parses a single top-level statement, or returns `None` once the input
+    /// is exhausted. Together with `position` this allows parsing a program
+    /// one statement at a time.
+    pub fn parse_next_statement(&mut self) -> Result<Option<CommentedStatement>, ParseError> {
+        self.skip_blank_lines();
+        match self.get_current_token() {
+            Token::EOF => return Ok(None),
+            // Blank lines yield no layout tokens, so these are real mistakes
+            Token::Indent => return Err(ParseError::new("Error: unexpected indent".to_string())),
+            Token::Dedent => return Err(ParseError::new("Error: unexpected dedent".to_string())),
+            _ => {}
+        }
+
+        self.parse_commented_statement().map(Some)
+    }
+
+    /// Parses a statement together with the comment lines above it and the
+    /// comment ending its line.
+    fn parse_commented_statement(&mut self) -> Result<CommentedStatement, ParseError> {
+        let leading = std::mem::take(&mut self.pending_comments);
+        let statement = self.parse_statement()?;
+        let trailing = std::mem::take(&mut self.line_comments);
+
+        Ok(CommentedStatement {
+            statement,
+            trivia: Trivia { leading, trailing },
+        })
+    }
+
+    /// Parses an indented block of statements. The current token must be the
+    /// `Indent` opening the block; parsing stops after its matching `Dedent`.
+    pub fn parse_block(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let body = self.parse_commented_block()?;
+        Ok(body.into_iter().map(|item| item.statement).collect())
+    }
+
+    /// Like `parse_block`, keeping the comments around each statement when
+    /// they are preserved. Comments after the last statement are left to
+    /// lead whatever statement follows the block.
+    fn parse_commented_block(&mut self) -> Result<Vec<CommentedStatement>, ParseError> {
+        self.expect(TokenKind::Indent, "Error: expected an indented block".to_string())?;
+
+        let mut body = Vec::new();
+        loop {
+            match self.get_current_token() {
+                Token::Dedent => {
+                    self.advance();
+                    break;
+                }
+                // The tokenizer closes every block, so the stream is malformed
+                Token::EOF => {
+                    return Err(ParseError::new(
+                        "Error: block is not closed by a dedent".to_string(),
+                    ))
+                }
+                Token::Newline | Token::Comment(_) => self.take_comment(),
+                Token::Indent => {
+                    return Err(ParseError::new("Error: unexpected indent".to_string()))
+                }
+                _ => body.push(self.parse_commented_statement()?),
+            }
+        }
+
+        Ok(body)
+    }
+
+    /// Index of the next token to be consumed.
+    pub fn position(&self) -> usize {
+        self.current_token
+    }
+
+    /// Tokens not consumed yet, including the final EOF.
+    pub fn remaining(&self) -> &[Token] {
+        self.tokens.get(self.current_token..).unwrap_or(&[])
+    }
+
+    /// Tokenizes and parses `source` as a whole file.
+    pub fn parse_module(source: &str) -> Result<Program, ParseError> {
+        let tokens = tokenize(source)?;
+        Parser::new(&tokens).parse()
+    }
+
+    /// Tokenizes and parses `source` as typed into a REPL so far. Unlike a
+    /// module, the input may stop partway through a statement: inside
+    /// brackets or a triple-quoted string, or in a compound statement, which
+    /// only a blank line ends.
+    pub fn parse_interactive(source: &str) -> Result<InteractiveInput, ParseError> {
+        let mut tokenizer = Tokenizer::new();
+        let mut tokens = tokenizer.tokenize_line(source)?;
+        if tokenizer.line_continues() {
+            return Ok(InteractiveInput::Incomplete);
+        }
+
+        let first_line = tokens
+            .split(|token| *token == Token::Newline)
+            .next()
+            .unwrap_or(&[]);
+        let header = first_line
+            .iter()
+            .rev()
+            .find(|token| !matches!(token, Token::Comment(_)));
+        let ends_with_blank_line = source
+            .strip_suffix('\n')
+            .and_then(|rest| rest.rsplit('\n').next())
+            .is_some_and(|line| line.trim().is_empty());
+        if header == Some(&Token::Colon) && !ends_with_blank_line {
+            return Ok(InteractiveInput::Incomplete);
+        }
+
+        tokens.extend(tokenizer.finish()?);
+        Parser::new(&tokens).parse().map(InteractiveInput::Complete)
+    }
+
+    /// Tokenizes and parses `source` as exactly one expression, rejecting
+    /// anything left over after it.
+    pub fn parse_expression_str(source: &str) -> Result<Expression, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser::new(&tokens);
+        // Indentation and comments around a lone expression carry no meaning
+        parser.skip_layout();
+        let expression = parser.parse_expression_node()?;
+
+        parser.skip_layout();
+        if parser.not_eof() {
+            return Err(ParseError::new(format!(
+                "Error: unexpected {} after expression",
+                describe(parser.get_current_token())
+            )));
+        }
+
+        Ok(expression)
+    }
+
+    /// Tokenizes and parses `source`, reporting each error to `on_error` as
+    /// it is found instead of stopping at the first one. After an error the
+    /// rest of the line is skipped and parsing resumes with the next
+    /// statement, unless `on_error` returns `false`. The statements that did
+    /// parse are returned. Unbalanced brackets are the exception: they are
+    /// the only error reported, and nothing is parsed.
+    pub fn parse_with<F>(source: &str, mut on_error: F) -> Program
+    where
+        F: FnMut(&ParseError) -> bool,
+    {
+        let tokens = match tokenize(source) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                on_error(&ParseError::from(err));
+                return Program::new(Vec::new());
+            }
+        };
+
+        // Unbalanced brackets also swallow the newlines after them, so there
+        // is no telling where the affected lines end. Report just that rather
+        // than the confusing errors that would follow.
+        if let Err(err) = validate_brackets(&tokens) {
+            on_error(&err);
+            return Program::new(Vec::new());
+        }
+
+        let mut parser = Parser::new(&tokens);
         let mut body = Vec::new();
+        loop {
+            let start = parser.position();
+            match parser.parse_next_statement() {
+                Ok(Some(statement)) => body.push(statement),
+                Ok(None) => break,
+                Err(err) => {
+                    if !on_error(&err) {
+                        break;
+                    }
+                    parser.recover(start);
+                }
+            }
+        }
+
+        Program {
+            body,
+            trailing_comments: Vec::new(),
+        }
+    }
+
+    /// Skips the rest of the statement that failed to parse from `start`,
+    /// along with any lines indented under it, so parsing can resume at the
+    /// top level.
+    fn recover(&mut self, start: usize) {
+        self.skip_to_next_line();
+
+        // The error may have been deep inside the statement's blocks, so
+        // count the layout from where the statement began
+        let mut depth = 0usize;
+        for token in self.tokens.get(start..self.current_token).unwrap_or(&[]) {
+            match token {
+                Token::Indent => depth += 1,
+                Token::Dedent => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+
         while self.not_eof() {
-            body.push(self.parse_statement());
+            match self.get_current_token() {
+                Token::Indent => depth += 1,
+                Token::Dedent => depth = depth.saturating_sub(1),
+                _ if depth == 0 => break,
+                _ => {}
+            }
+            self.advance();
         }
 
-        Program { body }
+        // Always make progress, or the same error would repeat forever
+        if self.current_token == start {
+            self.advance();
+        }
     }
 
-    fn parse_statement(&mut self) -> Statement {
-        self.parse_expression()
+    /// Advances past the rest of the current logical line.
+    fn skip_to_next_line(&mut self) {
+        // The failing token may itself have been the newline
+        let previous = self.current_token.checked_sub(1).and_then(|i| self.tokens.get(i));
+        if let Some(Token::Newline | Token::Semicolon) = previous {
+            return;
+        }
+
+        while self.not_eof() {
+            if let Token::Newline | Token::Semicolon = self.advance() {
+                break;
+            }
+        }
     }
 
-    fn parse_expression(&mut self) -> Statement {
-        self.parse_additive_expression()
+    /// Skips empty statements and comment-only lines at the top level.
+    fn skip_blank_lines(&mut self) {
+        while let Token::Newline | Token::Comment(_) = self.get_current_token() {
+            self.take_comment();
+        }
+    }
+
+    /// Skips every layout token, including indentation, for entry points
+    /// where it carries no meaning.
+    fn skip_layout(&mut self) {
+        while let Token::Newline | Token::Indent | Token::Dedent | Token::Comment(_) =
+            self.get_current_token()
+        {
+            self.take_comment();
+        }
+    }
+
+    /// Advances past the current token, remembering it if it is a comment
+    /// that should be kept as trivia.
+    fn take_comment(&mut self) {
+        if let Token::Comment(comment) = self.advance().clone() {
+            if self.preserve_comments {
+                self.pending_comments.push(comment);
+            }
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        // Statements never start inside brackets, whatever an earlier error
+        // left open
+        self.brackets = 0;
+        self.chained = 0;
+
+        // A compound statement ends with its block, not a terminator
+        if self.at_match_statement() {
+            return self.parse_match_statement();
+        }
+
+        let statement = match self.get_current_token() {
+            Token::RaiseKeyword => self.parse_raise_statement()?,
+            // Only between expressions is `@` matrix multiplication
+            Token::At => {
+                return Err(ParseError::new(
+                    "Error: decorators are not supported yet".to_string(),
+                ))
+            }
+            _ => self.parse_expression()?,
+        };
+
+        self.parse_statement_terminator()?;
+        Ok(statement)
+    }
+
+    /// Consumes the token ending a simple statement: a newline, a semicolon,
+    /// or nothing at the end of the input. A trailing comment is skipped.
+    fn parse_statement_terminator(&mut self) -> Result<(), ParseError> {
+        if let Token::Comment(comment) = self.get_current_token().clone() {
+            self.advance();
+            if self.preserve_comments {
+                self.line_comments.push(comment);
+            }
+        }
+
+        match self.get_current_token() {
+            Token::Newline | Token::Semicolon => {
+                self.advance();
+            }
+            Token::EOF => {}
+            token => {
+                return Err(ParseError::new(format!(
+                    "Error: expected end of statement, found {}",
+                    describe(token)
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_raise_statement(&mut self) -> Result<Statement, ParseError> {
+        self.expect(TokenKind::RaiseKeyword, "Error: expected 'raise'".to_string())?;
+
+        // A bare `raise` re-raises the exception currently being handled
+        let exception = match self.get_current_token() {
+            Token::Newline | Token::Semicolon | Token::EOF => None,
+            _ => Some(self.parse_expression_node()?),
+        };
+
+        let mut cause = None;
+        if exception.is_some() && *self.get_current_token() == Token::FromKeyword {
+            self.advance();
+            cause = Some(self.parse_expression_node()?);
+        }
+
+        Ok(Statement::RaiseStatement { exception, cause })
+    }
+
+    /// `match` is a soft keyword: it only starts a match statement when it
+    /// leads a line with a ':' after the subject, so `match` can still be used
+    /// as a name.
+    fn at_match_statement(&self) -> bool {
+        let line = self.remaining();
+        if !matches!(line.first(), Some(Token::Identifier(v)) if v == "match") {
+            return false;
+        }
+
+        let mut brackets = 0usize;
+        for (index, token) in line.iter().enumerate() {
+            match token {
+                Token::LParen | Token::LBracket | Token::LBrace => brackets += 1,
+                Token::RParen | Token::RBracket | Token::RBrace => {
+                    brackets = brackets.saturating_sub(1)
+                }
+                Token::Colon if brackets == 0 => return index > 1,
+                Token::Newline | Token::Semicolon | Token::EOF => return false,
+                _ => {}
+            }
+        }
+
+        false
+    }
+
+    /// Nested match statements recurse through their case blocks, so each
+    /// block counts towards the same limit as nested expressions.
+    fn parse_match_statement(&mut self) -> Result<Statement, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::new(format!(
+                "Error: block nesting too deep (limit is {})",
+                self.max_depth
+            )));
+        }
+
+        self.advance();
+        let subject = self.parse_expression_node()?;
+        self.expect(TokenKind::Colon, "Error: expected ':' after match subject".to_string())?;
+        if !self.at_block_start() {
+            return Err(ParseError::new(
+                "Error: the cases of a match statement must start on a new line".to_string(),
+            ));
+        }
+        self.parse_statement_terminator()?;
+        // The header's comment must not be taken by the statements in the cases
+        let header_comments = std::mem::take(&mut self.line_comments);
+        self.skip_block_comments();
+        self.expect(TokenKind::Indent, "Error: expected an indented block of cases".to_string())?;
+
+        let mut cases = Vec::new();
+        loop {
+            self.skip_block_comments();
+            match self.get_current_token() {
+                Token::Dedent => {
+                    self.advance();
+                    break;
+                }
+                Token::EOF => {
+                    return Err(ParseError::new(
+                        "Error: match block is not closed by a dedent".to_string(),
+                    ))
+                }
+                _ => cases.push(self.parse_match_case()?),
+            }
+        }
+
+        if cases.is_empty() {
+            return Err(ParseError::new("Error: match statement has no cases".to_string()));
+        }
+
+        self.line_comments = header_comments;
+        Ok(Statement::MatchStatement { subject, cases })
+    }
+
+    fn parse_match_case(&mut self) -> Result<MatchCase, ParseError> {
+        match self.advance() {
+            Token::Identifier(v) if v == "case" => {}
+            token => {
+                return Err(ParseError::new(format!(
+                    "Error: expected 'case' in match block, found {}",
+                    describe(token)
+                )))
+            }
+        }
+
+        // `case a, b:` is a sequence pattern without brackets
+        let mut pattern = self.parse_pattern()?;
+        if *self.get_current_token() == Token::Comma {
+            let mut patterns = vec![pattern];
+            while *self.get_current_token() == Token::Comma {
+                self.advance();
+                if *self.get_current_token() == Token::Colon {
+                    break;
+                }
+                patterns.push(self.parse_pattern()?);
+            }
+            pattern = Pattern::Sequence(patterns);
+        }
+
+        self.expect(TokenKind::Colon, "Error: expected ':' after case pattern".to_string())?;
+        if !self.at_block_start() {
+            return Ok(MatchCase {
+                pattern,
+                body: self.parse_inline_body()?,
+            });
+        }
+        self.parse_statement_terminator()?;
+        let header_comments = std::mem::take(&mut self.line_comments);
+        self.pending_comments.extend(header_comments);
+        self.skip_block_comments();
+
+        self.depth += 1;
+        let body = self.parse_commented_block();
+        self.depth -= 1;
+
+        Ok(MatchCase {
+            pattern,
+            body: body?,
+        })
+    }
+
+    /// Whether the rest of the line after a block header's ':' is empty, so an
+    /// indented block follows.
+    fn at_block_start(&mut self) -> bool {
+        matches!(
+            self.get_current_token(),
+            Token::Newline | Token::Comment(_) | Token::EOF
+        )
+    }
+
+    /// Parses the simple statements making up a body written on the header
+    /// line, as in `case 1: x; y`.
+    fn parse_inline_body(&mut self) -> Result<Vec<CommentedStatement>, ParseError> {
+        let mut body = Vec::new();
+        loop {
+            if self.at_match_statement() {
+                return Err(ParseError::new(
+                    "Error: a match statement can't follow ':' on the same line".to_string(),
+                ));
+            }
+            body.push(self.parse_commented_statement()?);
+
+            // Only a semicolon with more after it continues the line
+            let previous = self.current_token.checked_sub(1).and_then(|i| self.tokens.get(i));
+            if previous != Some(&Token::Semicolon) || self.at_block_start() {
+                break;
+            }
+        }
+
+        Ok(body)
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::new(format!(
+                "Error: pattern nesting too deep (limit is {})",
+                self.max_depth
+            )));
+        }
+
+        self.depth += 1;
+        let pattern = self.parse_closed_pattern();
+        self.depth -= 1;
+        pattern
+    }
+
+    fn parse_closed_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let pattern = match self.advance().clone() {
+            Token::Integer(v) => Pattern::Literal(Literal::Int(v)),
+            Token::Float(v) => Pattern::Literal(Literal::Float(v)),
+            Token::StringLiteral(v) => Pattern::Literal(Literal::String(v)),
+            Token::BooleanLiteral(v) => Pattern::Literal(Literal::Bool(v)),
+            Token::NoneLiteral => Pattern::Literal(Literal::None),
+            Token::Minus => match self.advance() {
+                Token::Integer(v) => Pattern::Literal(Literal::Int(-v)),
+                Token::Float(v) => Pattern::Literal(Literal::Float(-v)),
+                token => {
+                    return Err(ParseError::new(format!(
+                        "Error: expected a number after '-' in pattern, found {}",
+                        describe(token)
+                    )))
+                }
+            },
+            Token::Identifier(v) if v == "_" => Pattern::Wildcard,
+            Token::Identifier(v) => Pattern::Capture(Identifier { name: v }),
+            Token::LBracket => Pattern::Sequence(self.parse_sequence_pattern(Token::RBracket)?),
+            Token::LParen => {
+                // `(p)` only groups; a comma or `()` makes it a sequence
+                if *self.get_current_token() == Token::RParen {
+                    self.advance();
+                    return Ok(Pattern::Sequence(Vec::new()));
+                }
+
+                let first = self.parse_pattern()?;
+                if *self.get_current_token() == Token::RParen {
+                    self.advance();
+                    return Ok(first);
+                }
+
+                self.expect(TokenKind::Comma, "Error: expected ',' between patterns".to_string())?;
+                let mut patterns = vec![first];
+                patterns.append(&mut self.parse_sequence_pattern(Token::RParen)?);
+                Pattern::Sequence(patterns)
+            }
+            token => {
+                return Err(ParseError::new(format!(
+                    "Error: invalid pattern, found {}",
+                    describe(&token)
+                )))
+            }
+        };
+
+        Ok(pattern)
+    }
+
+    /// Parses comma-separated patterns up to and including `closing`, which
+    /// may follow a trailing comma.
+    fn parse_sequence_pattern(&mut self, closing: Token) -> Result<Vec<Pattern>, ParseError> {
+        let mut patterns = Vec::new();
+        while *self.get_current_token() != closing {
+            patterns.push(self.parse_pattern()?);
+            if *self.get_current_token() != closing {
+                self.expect(TokenKind::Comma, "Error: expected ',' between patterns".to_string())?;
+            }
+        }
+        self.advance();
+
+        Ok(patterns)
+    }
+
+    /// Skips newlines and comment-only lines between the parts of a compound
+    /// statement, keeping the comments as trivia for the next statement.
+    fn skip_block_comments(&mut self) {
+        while let Token::Newline | Token::Comment(_) = self.get_current_token() {
+            self.take_comment();
+        }
+    }
+
+    fn parse_expression(&mut self) -> Result<Statement, ParseError> {
+        self.check_depth(1)?;
+
+        self.depth += 1;
+        let expression = self.parse_logical_expression();
+        self.depth -= 1;
+
+        expression
+    }
+
+    /// Fails if `levels` more levels of nesting would exceed `max_depth`.
+    fn check_depth(&self, levels: usize) -> Result<(), ParseError> {
+        if self.depth + levels > self.max_depth {
+            return Err(ParseError::new(format!(
+                "Error: expression nesting too deep (limit is {})",
+                self.max_depth
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Counts one more operation chained onto a left operand, failing once a
+    /// statement has more than `MAX_CHAINED` of them.
+    fn chain_operation(&mut self) -> Result<(), ParseError> {
+        self.chained += 1;
+        if self.chained > MAX_CHAINED {
+            return Err(ParseError::new(format!(
+                "Error: expression chains too many operations (limit is {})",
+                MAX_CHAINED
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn parse_expression_node(&mut self) -> Result<Expression, ParseError> {
+        let statement = self.parse_expression()?;
+        Self::into_expression(statement)
+    }
+
+    fn into_expression(statement: Statement) -> Result<Expression, ParseError> {
+        match statement {
+            Statement::ExpressionStatement(expression) => Ok(expression),
+            _ => Err(ParseError::new(
+                "Error: expected an expression, found a statement".to_string(),
+            )),
+        }
+    }
+
+    /// Wraps `operand` in the prefix operators collected before it, innermost
+    /// (closest to the operand) last in `operators`.
+    fn apply_unary_operators(
+        operators: Vec<Operator>,
+        operand: Statement,
+    ) -> Result<Statement, ParseError> {
+        let mut node = operand;
+        for operator in operators.into_iter().rev() {
+            let operand = Self::into_expression(node)?;
+            node = Statement::ExpressionStatement(Expression::UnaryExpression(
+                Box::new(operand),
+                operator,
+            ));
+        }
+
+        Ok(node)
+    }
+
+    /// `and`/`or` get their own node rather than a BinaryExpression since the
+    /// right operand is only evaluated depending on the left one.
+    fn parse_logical_expression(&mut self) -> Result<Statement, ParseError> {
+        let mut left = self.parse_logical_and_expression()?;
+
+        while *self.get_current_token() == Token::Or {
+            self.advance();
+            self.chain_operation()?;
+            let right = self.parse_logical_and_expression()?;
+            left = Statement::ExpressionStatement(Expression::LogicalExpression {
+                op: Operator::Or,
+                left: Box::new(left),
+                right: Box::new(right),
+            })
+        }
+
+        Ok(left)
+    }
+
+    fn parse_logical_and_expression(&mut self) -> Result<Statement, ParseError> {
+        let mut left = self.parse_logical_not_expression()?;
+
+        while *self.get_current_token() == Token::And {
+            self.advance();
+            self.chain_operation()?;
+            let right = self.parse_logical_not_expression()?;
+            left = Statement::ExpressionStatement(Expression::LogicalExpression {
+                op: Operator::And,
+                left: Box::new(left),
+                right: Box::new(right),
+            })
+        }
+
+        Ok(left)
+    }
+
+    /// `not` binds looser than comparisons, so `not a == b` is `not (a == b)`.
+    /// Prefix operators are collected in a loop rather than by recursion.
+    fn parse_logical_not_expression(&mut self) -> Result<Statement, ParseError> {
+        let mut operators = Vec::new();
+        while *self.get_current_token() == Token::Not {
+            self.advance();
+            operators.push(Operator::Not);
+        }
+        // Each operator nests the operand one level deeper
+        self.check_depth(operators.len())?;
+
+        let operand = self.parse_comparison_expression()?;
+        Self::apply_unary_operators(operators, operand)
+    }
+
+    /// Comparisons chain rather than nest: `a < b < c` compares `b` with both
+    /// neighbours instead of comparing the result of `a < b` with `c`.
+    fn parse_comparison_expression(&mut self) -> Result<Statement, ParseError> {
+        let left = self.parse_additive_expression()?;
+        let mut comparisons = Vec::new();
+
+        while let Token::DoubleEquals
+        | Token::NotEquals
+        | Token::LessThan
+        | Token::GreaterThan
+        | Token::LessThanOrEqual
+        | Token::GreaterThanOrEqual
+        | Token::Is
+        | Token::IsNot
+        | Token::In
+        | Token::NotIn = self.get_current_token()
+        {
+            let operator = match self.advance() {
+                Token::DoubleEquals => Operator::Equal,
+                Token::NotEquals => Operator::NotEqual,
+                Token::LessThan => Operator::LessThan,
+                Token::GreaterThan => Operator::GreaterThan,
+                Token::LessThanOrEqual => Operator::LessThanOrEqual,
+                Token::GreaterThanOrEqual => Operator::GreaterThanOrEqual,
+                Token::Is => Operator::Is,
+                Token::IsNot => Operator::IsNot,
+                Token::In => Operator::In,
+                Token::NotIn => Operator::NotIn,
+                _ => return Err(ParseError::new("Error: invalid operator".to_string())),
+            };
+
+            let right = self.parse_additive_expression()?;
+            comparisons.push((operator, right));
+        }
+
+        let expression = match comparisons.len() {
+            0 => return Ok(left),
+            1 => {
+                let (operator, right) = comparisons.remove(0);
+                Expression::BinaryExpression(Box::new(left), operator, Box::new(right))
+            }
+            _ => Expression::ComparisonChain {
+                left: Box::new(left),
+                comparisons,
+            },
+        };
+
+        Ok(Statement::ExpressionStatement(expression))
     }
 
-    fn parse_multiplicative_expression(&mut self) -> Statement{
-        let mut left = self.parse_primary();
+    fn parse_multiplicative_expression(&mut self) -> Result<Statement, ParseError> {
+        let mut left = self.parse_unary_expression()?;
 
-        while *self.get_current_token() == Token::Star || *self.get_current_token() == Token::Slash {
+        while let Token::Star | Token::Slash | Token::Percent | Token::At = self.get_current_token() {
+            self.chain_operation()?;
             let operator = match self.advance() {
                 Token::Star => Operator::Multiply,
                 Token::Slash => Operator::Divide,
                 Token::Percent => Operator::Modulus,
-                Token::DoubleStar => Operator::Exponent,
-                _ => panic!("Invalid operator")
+                Token::At => Operator::MatMul,
+                _ => return Err(ParseError::new("Error: invalid operator".to_string()))
             };
 
-            let right = self.parse_primary();
+            let right = self.parse_unary_expression()?;
             left = Statement::ExpressionStatement(Expression::BinaryExpression(Box::new(left), operator, Box::new(right)))
         }  
 
-        left 
+        Ok(left)
     }
 
-    fn parse_additive_expression(&mut self) -> Statement {
-        let mut left = self.parse_multiplicative_expression();
+    fn parse_additive_expression(&mut self) -> Result<Statement, ParseError> {
+        let mut left = self.parse_multiplicative_expression()?;
 
         while *self.get_current_token() == Token::Plus || *self.get_current_token() == Token::Minus {
+            self.chain_operation()?;
             let operator = match self.advance() {
                 Token::Plus => Operator::Add,
                 Token::Minus => Operator::Subtract,
-                _ => panic!("Invalid operator")
+                _ => return Err(ParseError::new("Error: invalid operator".to_string()))
             };
 
-            let right = self.parse_multiplicative_expression();
+            let right = self.parse_multiplicative_expression()?;
             left = Statement::ExpressionStatement(Expression::BinaryExpression(Box::new(left), operator, Box::new(right)))
         }  
 
-        left 
+        Ok(left)
     }
 
-    fn parse_primary(&mut self) -> Statement {
-        let current_token = self.advance();
+    fn parse_unary_expression(&mut self) -> Result<Statement, ParseError> {
+        let mut operators = Vec::new();
+        while let Token::Minus | Token::Plus = self.get_current_token() {
+            let operator = match self.advance() {
+                Token::Minus => Operator::Subtract,
+                _ => Operator::Add,
+            };
+            operators.push(operator);
+        }
+        self.check_depth(operators.len())?;
+
+        let operand = self.parse_power_expression()?;
+        Self::apply_unary_operators(operators, operand)
+    }
+
+    /// `**` binds tighter than a unary operator on its left but not on its
+    /// right, so `-x ** -y` is `-(x ** (-y))`. It is right associative.
+    fn parse_power_expression(&mut self) -> Result<Statement, ParseError> {
+        let base = self.parse_postfix_expression()?;
+        if *self.get_current_token() != Token::DoubleStar {
+            return Ok(base);
+        }
+
+        self.advance();
+        self.check_depth(1)?;
+        self.depth += 1;
+        let exponent = self.parse_unary_expression();
+        self.depth -= 1;
+
+        Ok(Statement::ExpressionStatement(Expression::BinaryExpression(
+            Box::new(base),
+            Operator::Exponent,
+            Box::new(exponent?),
+        )))
+    }
+
+    /// Applies attribute access, calls and subscripts to a primary expression,
+    /// in source order, so chains like `obj.method()[0].field` nest correctly.
+    fn parse_postfix_expression(&mut self) -> Result<Statement, ParseError> {
+        let mut object = self.parse_primary()?;
+
+        loop {
+            object = match self.get_current_token() {
+                Token::Dot => {
+                    self.advance();
+                    self.chain_operation()?;
+                    let property = self.expect_identifier("after '.'")?;
+                    Statement::ExpressionStatement(Expression::MemberExpression(
+                        Box::new(object),
+                        property,
+                    ))
+                }
+                Token::LParen => {
+                    self.advance();
+                    self.chain_operation()?;
+                    let arguments = self.parse_function_arguments()?;
+                    Statement::ExpressionStatement(Expression::FunctionCallExpression(
+                        Box::new(object),
+                        arguments,
+                    ))
+                }
+                Token::LBracket => {
+                    self.advance();
+                    self.chain_operation()?;
+                    let index = self.parse_expression()?;
+                    self.expect(TokenKind::RBracket, "Error: missing closing bracket".to_string())?;
+                    Statement::ExpressionStatement(Expression::SubscriptExpression(
+                        Box::new(object),
+                        Box::new(index),
+                    ))
+                }
+                _ => break,
+            };
+        }
+
+        Ok(object)
+    }
+
+    /// Parses a comma separated argument list, consuming the closing
+    /// parenthesis. The opening one must already be consumed.
+    fn parse_function_arguments(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let mut arguments = Vec::new();
+
+        while *self.get_current_token() != Token::RParen {
+            let mut argument = self.parse_expression()?;
+
+            // A generator as the only argument needs no parentheses of its own
+            if *self.get_current_token() == Token::ForKeyword {
+                if !arguments.is_empty() {
+                    return Err(ParseError::new(
+                        "Error: generator expression must be parenthesized".to_string(),
+                    ));
+                }
+                argument = self.parse_generator_expression(argument)?;
+                self.expect(
+                    TokenKind::RParen,
+                    "Error: generator expression must be parenthesized".to_string(),
+                )?;
+                return Ok(vec![argument]);
+            }
+
+            arguments.push(argument);
 
-        println!("Token : {:?}", current_token);
+            if *self.get_current_token() != Token::RParen {
+                self.expect(TokenKind::Comma, "Error: expected ',' between arguments".to_string())?;
+            }
+        }
+
+        self.expect(TokenKind::RParen, "Error: missing closing parenthesis".to_string())?;
+        Ok(arguments)
+    }
+
+    /// Parses the `for target in iter if condition...` part of a generator
+    /// expression whose element has already been parsed.
+    fn parse_generator_expression(&mut self, element: Statement) -> Result<Statement, ParseError> {
+        self.expect(TokenKind::ForKeyword, "Error: expected 'for'".to_string())?;
+        let target = self.expect_identifier("after 'for'")?;
+        self.expect(TokenKind::In, "Error: expected 'in' after the loop target".to_string())?;
+        let iter = self.parse_expression()?;
+
+        let mut conditions = Vec::new();
+        while *self.get_current_token() == Token::IfKeyword {
+            self.advance();
+            conditions.push(self.parse_expression()?);
+        }
+
+        Ok(Statement::ExpressionStatement(Expression::GeneratorExpression {
+            element: Box::new(element),
+            target,
+            iter: Box::new(iter),
+            conditions,
+        }))
+    }
+
+    fn parse_primary(&mut self) -> Result<Statement, ParseError> {
+        let current_token = self.advance();
 
         let node = match current_token {
             Token::Identifier(v) => {
@@ -174,14 +1133,782 @@ impl Parser {
             Token::StringLiteral(v) => {
                 Statement::ExpressionStatement(Expression::Literal(Literal::String(v.to_string())))
             }
+            Token::BooleanLiteral(v) => {
+                Statement::ExpressionStatement(Expression::Literal(Literal::Bool(*v)))
+            }
+            Token::NoneLiteral => {
+                Statement::ExpressionStatement(Expression::Literal(Literal::None))
+            }
             Token::LParen => {
-                let value = self.parse_expression();
-                self.expect(Token::RParen, "Error: missing closing parenthesis".to_string());
+                let mut value = self.parse_expression()?;
+                if *self.get_current_token() == Token::ForKeyword {
+                    value = self.parse_generator_expression(value)?;
+                }
+                self.expect(TokenKind::RParen, "Error: missing closing parenthesis".to_string())?;
                 value
             },
-            _ => panic!("Undefined Symbol encountered while parsing, {:?}", current_token),
+            _ => {
+                return Err(ParseError::new(format!(
+                    "Error: unexpected {}",
+                    describe(current_token)
+                )))
+            }
+        };
+
+        Ok(node)
+    }
+}
+
+/// Checks that `()`, `[]` and `{}` in `tokens` are balanced and properly
+/// nested, reporting the first offending bracket along with the tokens
+/// around it. This is cheap to run before parsing and gives a clearer
+/// message than the error the parser would hit further on.
+pub fn validate_brackets(tokens: &[Token]) -> Result<(), ParseError> {
+    let mut open: Vec<(usize, char)> = Vec::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        let closing = match token {
+            Token::LParen => {
+                open.push((index, '('));
+                continue;
+            }
+            Token::LBracket => {
+                open.push((index, '['));
+                continue;
+            }
+            Token::LBrace => {
+                open.push((index, '{'));
+                continue;
+            }
+            Token::RParen => ('(', ')'),
+            Token::RBracket => ('[', ']'),
+            Token::RBrace => ('{', '}'),
+            _ => continue,
+        };
+
+        match open.pop() {
+            Some((_, opening)) if opening == closing.0 => {}
+            Some((start, opening)) => {
+                return Err(ParseError::new(format!(
+                    "Error: closing '{}' does not match opening '{}' in `{}`",
+                    closing.1,
+                    opening,
+                    source_around(tokens, start, index)
+                )))
+            }
+            None => {
+                return Err(ParseError::new(format!(
+                    "Error: unmatched '{}' in `{}`",
+                    closing.1,
+                    source_around(tokens, index, index)
+                )))
+            }
+        }
+    }
+
+    if let Some((start, opening)) = open.pop() {
+        return Err(ParseError::new(format!(
+            "Error: '{}' was never closed in `{}`",
+            opening,
+            source_around(tokens, start, start)
+        )));
+    }
+
+    Ok(())
+}
+
+/// Names a token in an error message: source text is quoted, while layout
+/// tokens already render as placeholders like `<newline>`.
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Newline | Token::Indent | Token::Dedent | Token::EOF => token.to_string(),
+        _ => format!("'{}'", token),
+    }
+}
+
+/// Renders the tokens from `first` to `last` with a few more on either side
+/// from the same line, to point at a spot in the source while tokens carry
+/// no positions.
+fn source_around(tokens: &[Token], first: usize, last: usize) -> String {
+    const CONTEXT: usize = 3;
+    let in_line = |token: &Token| {
+        !matches!(token, Token::Newline | Token::Indent | Token::Dedent | Token::EOF)
+    };
+
+    let mut start = first;
+    while start > 0 && first - start < CONTEXT && in_line(&tokens[start - 1]) {
+        start -= 1;
+    }
+    let mut end = last + 1;
+    while end < tokens.len() && end - last <= CONTEXT && in_line(&tokens[end]) {
+        end += 1;
+    }
+
+    tokens[start..end]
+        .iter()
+        .map(|token| token.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::ast::dump;
+
+    fn parse_source(source: &str) -> Result<Program, ParseError> {
+        let tokens = tokenize(source)?;
+        Parser::new(&tokens).parse()
+    }
+
+    fn dump_source(source: &str) -> String {
+        dump(&parse_source(source).unwrap())
+    }
+
+    fn parse_error(source: &str) -> String {
+        parse_source(source).unwrap_err().message
+    }
+
+    #[test]
+    fn parse_error_converts_into_box_dyn_error() {
+        fn parse_boxed(source: &str) -> Result<Program, Box<dyn std::error::Error>> {
+            Ok(parse_source(source)?)
+        }
+
+        let err = parse_boxed("f(x\n").unwrap_err();
+        assert_eq!(err.to_string(), "Error: '(' was never closed in `f ( x`");
+        assert!(err.source().is_none());
+
+        // A tokenizer error stays reachable as the cause
+        let err = parse_boxed("a <> b\n").unwrap_err();
+        let message = "Error: '<>' is not a valid operator, use '!=' instead";
+        assert_eq!(err.to_string(), message);
+        assert_eq!(err.source().unwrap().to_string(), message);
+    }
+
+    #[test]
+    fn deeply_nested_parentheses_are_rejected() {
+        let source = format!("{}x{}", "(".repeat(1000), ")".repeat(1000));
+        assert_eq!(
+            parse_error(&source),
+            "Error: expression nesting too deep (limit is 64)"
+        );
+    }
+
+    #[test]
+    fn nesting_within_the_limit_parses() {
+        let source = format!("{}x{}", "(".repeat(60), ")".repeat(60));
+        assert_eq!(dump_source(&source), "x");
+    }
+
+    #[test]
+    fn long_prefix_operator_runs_count_towards_the_limit() {
+        let source = format!("{}x", "-".repeat(1000));
+        assert_eq!(
+            parse_error(&source),
+            "Error: expression nesting too deep (limit is 64)"
+        );
+    }
+
+    #[test]
+    fn module_docstring_is_the_leading_string() {
+        let program = parse_source("\"\"\"Module docs.\n\nMore.\"\"\"\nx\n").unwrap();
+        assert_eq!(program.docstring(), Some("Module docs.\n\nMore."));
+        assert_eq!(
+            parse_source("x\n\"\"\"not docs\"\"\"\n").unwrap().docstring(),
+            None
+        );
+    }
+
+    fn dump_expression_str(source: &str) -> String {
+        let expression = Parser::parse_expression_str(source).unwrap();
+        dump(&Program::new(vec![Statement::ExpressionStatement(expression)]))
+    }
+
+    fn parse_interactive(source: &str) -> Option<String> {
+        match Parser::parse_interactive(source).unwrap() {
+            InteractiveInput::Complete(program) => Some(dump(&program)),
+            InteractiveInput::Incomplete => None,
+        }
+    }
+
+    #[test]
+    fn interactive_simple_statement_is_complete_at_its_newline() {
+        assert_eq!(parse_interactive("f(x)\n"), Some("(call f x)".to_string()));
+        assert_eq!(
+            dump(&Parser::parse_module("f(x)\n").unwrap()),
+            "(call f x)"
+        );
+    }
+
+    #[test]
+    fn interactive_compound_statement_needs_a_blank_line() {
+        let mut source = "match x:\n".to_string();
+        assert_eq!(parse_interactive(&source), None);
+        source.push_str("    case 1:\n        y\n");
+        assert_eq!(parse_interactive(&source), None);
+        source.push('\n');
+        assert_eq!(
+            parse_interactive(&source),
+            Some("(match x (case 1 y))".to_string())
+        );
+    }
+
+    #[test]
+    fn interactive_open_brackets_and_strings_need_more_input() {
+        assert_eq!(parse_interactive("f(a,\n"), None);
+        assert_eq!(parse_interactive("'''doc\n"), None);
+        assert_eq!(parse_interactive("f(a,\n  b)\n"), Some("(call f a b)".to_string()));
+    }
+
+    #[test]
+    fn interactive_syntax_errors_are_not_incomplete() {
+        let err = Parser::parse_interactive("a +\n").unwrap_err();
+        assert_eq!(err.message, "Error: unexpected <newline>");
+    }
+
+    #[test]
+    fn parse_expression_str_respects_precedence() {
+        assert_eq!(dump_expression_str("1 + 2 * 3"), "(+ 1 (* 2 3))");
+    }
+
+    #[test]
+    fn parse_expression_str_ignores_surrounding_layout() {
+        assert_eq!(dump_expression_str("  1 + 2"), "(+ 1 2)");
+        assert_eq!(dump_expression_str("# c\n1 + 2\n"), "(+ 1 2)");
+    }
+
+    #[test]
+    fn parse_expression_str_rejects_trailing_input() {
+        let err = Parser::parse_expression_str("1 + 2 extra").unwrap_err();
+        assert_eq!(err.message, "Error: unexpected 'extra' after expression");
+    }
+
+    #[test]
+    fn parenthesized_generator_expression() {
+        assert_eq!(
+            dump_source("(x * 2 for x in xs if x > 1)\n"),
+            "(generator (* x 2) (for x xs) (if (> x 1)))"
+        );
+    }
+
+    #[test]
+    fn generator_as_sole_call_argument() {
+        assert_eq!(
+            dump_source("sum(x for x in xs)\n"),
+            "(call sum (generator x (for x xs)))"
+        );
+        assert_eq!(
+            parse_error("f(a, x for x in xs)\n"),
+            "Error: generator expression must be parenthesized"
+        );
+    }
+
+    #[test]
+    fn match_with_literal_patterns() {
+        let source = "match code:\n    case 200:\n        ok\n    case -1:\n        failed\n    case \"x\":\n        x\n    case None:\n        other\n";
+        assert_eq!(
+            dump_source(source),
+            "(match code (case 200 ok) (case -1 failed) (case \"x\" x) (case None other))"
+        );
+    }
+
+    #[test]
+    fn match_with_capture_wildcard_and_sequence_patterns() {
+        let source = "match point:\n    case (0, y):\n        y\n    case [x, [_, z]], w:\n        x\n    case other:\n        other\n    case _:\n        raise E\n";
+        assert_eq!(
+            dump_source(source),
+            "(match point (case [0 y] y) (case [[x [_ z]] w] x) (case other other) (case _ (raise E)))"
+        );
+    }
+
+    const THREE_NESTED_MATCHES: &str = "\
+match a:
+    case 1:
+        match b:
+            case 2:
+                match c:
+                    case 3:
+                        x
+";
+
+    #[test]
+    fn three_nested_blocks_close_before_a_top_level_statement() {
+        assert_eq!(
+            dump_source(&format!("{}y\n", THREE_NESTED_MATCHES)),
+            "(match a (case 1 (match b (case 2 (match c (case 3 x))))))\ny"
+        );
+    }
+
+    #[test]
+    fn three_nested_blocks_close_at_eof() {
+        let expected = "(match a (case 1 (match b (case 2 (match c (case 3 x))))))";
+        assert_eq!(dump_source(THREE_NESTED_MATCHES), expected);
+        assert_eq!(dump_source(THREE_NESTED_MATCHES.trim_end()), expected);
+    }
+
+    #[test]
+    fn deeply_nested_matches_are_rejected() {
+        let mut source = String::new();
+        for level in 0..200 {
+            let indent = " ".repeat(level * 2);
+            source.push_str(&format!("{}match x:\n{} case 1:\n", indent, indent));
+        }
+        source.push_str(&format!("{}y\n", " ".repeat(400)));
+        assert_eq!(parse_error(&source), "Error: block nesting too deep (limit is 64)");
+    }
+
+    #[test]
+    fn case_body_on_the_header_line() {
+        let source = "match x:\n    case 1: y\n    case 2: a; b  # two\n    case _:\n        z\n";
+        assert_eq!(
+            dump_source(source),
+            "(match x (case 1 y) (case 2 a b) (case _ z))"
+        );
+        assert_eq!(
+            parse_error("match x:\n    case 1: match y:\n        case 2:\n            z\n"),
+            "Error: a match statement can't follow ':' on the same line"
+        );
+    }
+
+    #[test]
+    fn cases_on_the_match_line_are_rejected() {
+        assert_eq!(
+            parse_error("match x: case 1: y\n"),
+            "Error: the cases of a match statement must start on a new line"
+        );
+    }
+
+    #[test]
+    fn match_stays_usable_as_a_name() {
+        assert_eq!(dump_source("match(x)\nmatch.group\n"), "(call match x)\n(. match group)");
+    }
+
+    fn parse_with_comments(source: &str) -> Program {
+        let tokens = tokenize(source).unwrap();
+        Parser::new(&tokens).with_comments(true).parse().unwrap()
+    }
+
+    #[test]
+    fn comments_attach_to_the_statements_they_surround() {
+        let program = parse_with_comments("# about f\nf()  # call f\ng()\n# the end\n");
+        assert_eq!(program.body[0].trivia.leading, vec![" about f"]);
+        assert_eq!(program.body[0].trivia.trailing, vec![" call f"]);
+        assert!(program.body[1].trivia.leading.is_empty());
+        assert!(program.body[1].trivia.trailing.is_empty());
+        assert_eq!(program.trailing_comments, vec![" the end"]);
+    }
+
+    #[test]
+    fn comments_are_dropped_unless_preserved() {
+        let program = parse_source("# about f\nf()  # call f\n").unwrap();
+        assert!(program.body[0].trivia.leading.is_empty());
+        assert!(program.body[0].trivia.trailing.is_empty());
+        assert!(program.trailing_comments.is_empty());
+    }
+
+    #[test]
+    fn comments_inside_brackets_trail_their_statement() {
+        let program = parse_with_comments("f(a,  # note\n  b)  # end\ng(c)\n");
+        assert_eq!(dump(&program), "(call f a b)\n(call g c)");
+        assert_eq!(program.body[0].trivia.trailing, vec![" note", " end"]);
+        assert!(program.body[1].trivia.leading.is_empty());
+    }
+
+    #[test]
+    fn comments_inside_a_match_stay_in_their_case() {
+        let source = "match x:  # header\n    # first\n    case 1:\n        # above y\n        y  # inner\nz\n";
+        let program = parse_with_comments(source);
+        assert!(program.body[0].trivia.leading.is_empty());
+        assert_eq!(program.body[0].trivia.trailing, vec![" header"]);
+        assert!(program.body[1].trivia.trailing.is_empty());
+
+        let Statement::MatchStatement { cases, .. } = &program.body[0].statement else {
+            panic!("expected a match statement");
         };
+        assert_eq!(cases[0].body[0].trivia.leading, vec![" first", " above y"]);
+        assert_eq!(cases[0].body[0].trivia.trailing, vec![" inner"]);
+    }
+
+    #[test]
+    fn blank_lines_between_top_level_statements() {
+        assert_eq!(dump_source("\n\nf()\n\n\n  \ng()\n\n"), "(call f)\n(call g)");
+    }
+
+    #[test]
+    fn stray_top_level_indent_is_an_error() {
+        assert_eq!(parse_error("x\n    y\n"), "Error: unexpected indent");
+    }
+
+    #[test]
+    fn parse_with_recovers_after_a_stray_indent() {
+        let mut errors = Vec::new();
+        let program = Parser::parse_with("x\n    y\n        w\nz\n", |err| {
+            errors.push(err.message.clone());
+            true
+        });
+        assert_eq!(errors, vec!["Error: unexpected indent"]);
+        assert_eq!(dump(&program), "x\nz");
+    }
+
+    #[test]
+    fn expect_matches_any_integer_by_kind() {
+        let mut parser = Parser::new(&[Token::Integer(7), Token::Integer(8)]);
+        for value in [7, 8] {
+            let token = parser.expect(TokenKind::Integer, "Error: expected an integer".to_string());
+            assert_eq!(token.unwrap(), Token::Integer(value));
+        }
+
+        let err = parser
+            .expect(TokenKind::Integer, "Error: expected an integer".to_string())
+            .unwrap_err();
+        assert_eq!(err.message, "Error: expected an integer");
+    }
+
+    #[test]
+    fn bare_raise() {
+        assert_eq!(dump_source("raise\n"), "(raise)");
+    }
+
+    #[test]
+    fn raise_with_exception() {
+        assert_eq!(
+            dump_source("raise ValueError(\"msg\")\n"),
+            "(raise (call ValueError \"msg\"))"
+        );
+    }
+
+    #[test]
+    fn raise_with_cause() {
+        assert_eq!(dump_source("raise X from Y\n"), "(raise X (from Y))");
+    }
+
+    #[test]
+    fn consecutive_top_level_calls() {
+        assert_eq!(
+            dump_source("print(\"hi\")\nprint(\"there\")\n"),
+            "(call print \"hi\")\n(call print \"there\")"
+        );
+    }
+
+    #[test]
+    fn method_call_on_parenthesized_number() {
+        assert_eq!(dump_source("(3).bit_length()\n"), "(call (. 3 bit_length))");
+    }
+
+    #[test]
+    fn method_call_on_string_literal() {
+        assert_eq!(dump_source("\"abc\".upper()\n"), "(call (. \"abc\" upper))");
+    }
+
+    #[test]
+    fn trailing_comma_in_call_arguments() {
+        assert_eq!(dump_source("f(1, 2,)\n"), "(call f 1 2)");
+    }
+
+    #[test]
+    fn comment_inside_a_multi_line_call() {
+        assert_eq!(
+            dump_source("f(a,  # first\n  b[0  # index\n  ])\n"),
+            "(call f a (subscript b 0))"
+        );
+    }
+
+    #[test]
+    fn lone_comma_in_call_arguments_is_rejected() {
+        assert_eq!(
+            parse_error("f(,)\n"),
+            "Error: unexpected ','"
+        );
+    }
+
+    #[test]
+    fn empty_and_blank_sources_parse_to_an_empty_program() {
+        for source in ["", "\n\n", "   "] {
+            assert!(parse_source(source).unwrap().body.is_empty(), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn empty_token_slice_does_not_panic() {
+        assert!(Parser::new(&[]).parse().unwrap().body.is_empty());
+        assert!(Parser::new(&[]).parse_next_statement().unwrap().is_none());
+        assert!(Parser::new(&[Token::Plus]).parse().is_err());
+    }
+
+    #[test]
+    fn parse_standalone_block() {
+        let tokens = tokenize("    x\n    f(y)\n").unwrap();
+        let body = Parser::new(&tokens).parse_block().unwrap();
+        assert_eq!(dump(&Program::new(body)), "x\n(call f y)");
+    }
+
+    #[test]
+    fn block_without_closing_dedent_is_rejected() {
+        let tokens = [
+            Token::Indent,
+            Token::Identifier("x".to_string()),
+            Token::Newline,
+            Token::EOF,
+        ];
+        let err = Parser::new(&tokens).parse_block().unwrap_err();
+        assert_eq!(err.message, "Error: block is not closed by a dedent");
+    }
+
+    fn collect_errors(source: &str) -> (Vec<String>, String) {
+        let mut errors = Vec::new();
+        let program = Parser::parse_with(source, |err| {
+            errors.push(err.message.clone());
+            true
+        });
+        (errors, dump(&program))
+    }
+
+    #[test]
+    fn parse_with_reports_every_error() {
+        let (errors, program) = collect_errors("a +\nok\nb c\n+\nd\n");
+        assert_eq!(
+            errors,
+            vec![
+                "Error: unexpected <newline>",
+                "Error: expected end of statement, found 'c'",
+                "Error: unexpected <newline>",
+            ]
+        );
+        assert_eq!(program, "ok\nd");
+    }
+
+    #[test]
+    fn parse_with_recovers_after_an_error_inside_a_case_body() {
+        let source = "match x:\n    case 1:\n        a +\n        b\n    case 2:\n        d\nc\n";
+        let (errors, program) = collect_errors(source);
+        assert_eq!(errors, vec!["Error: unexpected <newline>"]);
+        assert_eq!(program, "c");
+    }
+
+    #[test]
+    fn parse_with_stops_when_the_callback_says_so() {
+        let mut calls = 0;
+        Parser::parse_with("a +\nb c\n+\n", |_| {
+            calls += 1;
+            false
+        });
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn parse_with_reports_only_unbalanced_brackets() {
+        let (errors, _) = collect_errors("a\nb )\nc\n");
+        assert_eq!(errors, vec!["Error: unmatched ')' in `b )`"]);
+
+        let (errors, _) = collect_errors("f(x\n");
+        assert_eq!(errors, vec!["Error: '(' was never closed in `f ( x`"]);
+    }
+
+    #[test]
+    fn unclosed_bracket_is_reported() {
+        assert_eq!(
+            parse_error("x = 1\nf(a, [b\n"),
+            "Error: '[' was never closed in `( a , [ b`"
+        );
+    }
+
+    #[test]
+    fn unmatched_closing_bracket_is_reported() {
+        assert_eq!(parse_error("f(a))\n"), "Error: unmatched ')' in `( a ) )`");
+    }
+
+    #[test]
+    fn mismatched_bracket_types_are_reported() {
+        assert_eq!(
+            parse_error("f(a[1)]\n"),
+            "Error: closing ')' does not match opening '[' in `f ( a [ 1 ) ]`"
+        );
+    }
+
+    #[test]
+    fn balanced_brackets_pass_validation() {
+        assert!(validate_brackets(&tokenize("f(a[1], {b: (c)})\n").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn matrix_multiplication_between_expressions() {
+        assert_eq!(dump_source("m1 @ m2 * k\n"), "(* (@ m1 m2) k)");
+    }
+
+    #[test]
+    fn leading_at_is_a_decorator() {
+        assert_eq!(
+            parse_error("@decorator\n"),
+            "Error: decorators are not supported yet"
+        );
+    }
+
+    #[test]
+    fn exponent_is_right_associative_and_binds_tighter_than_unary() {
+        assert_eq!(dump_source("a ** 3\n"), "(** a 3)");
+        assert_eq!(dump_source("2 ** 3 ** 2\n"), "(** 2 (** 3 2))");
+        assert_eq!(dump_source("-x ** -y * z\n"), "(* (- (** x (- y))) z)");
+        assert_eq!(dump_source("f(x)[0] ** 2\n"), "(** (subscript (call f x) 0) 2)");
+    }
+
+    #[test]
+    fn long_exponent_chains_are_rejected() {
+        let source = format!("x{}\n", " ** x".repeat(1000));
+        assert_eq!(
+            parse_error(&source),
+            "Error: expression nesting too deep (limit is 64)"
+        );
+    }
+
+    #[test]
+    fn long_operator_chains_are_rejected() {
+        let source = format!("x{}\n", " + x".repeat(20000));
+        assert_eq!(
+            parse_error(&source),
+            "Error: expression chains too many operations (limit is 256)"
+        );
+        assert_eq!(dump_source(&format!("x{}\n", " + x".repeat(3))), "(+ (+ (+ x x) x) x)");
+    }
+
+    #[test]
+    fn long_call_chains_are_rejected() {
+        let source = format!("f{}\n", "()".repeat(100_000));
+        assert_eq!(
+            parse_error(&source),
+            "Error: expression chains too many operations (limit is 256)"
+        );
+    }
+
+    #[test]
+    fn chain_limit_applies_per_statement() {
+        let line = format!("x{}\n", " + x".repeat(200));
+        assert!(parse_source(&line.repeat(3)).is_ok());
+    }
+
+    #[test]
+    fn unary_minus_at_top_level() {
+        assert_eq!(dump_source("-x\n"), "(- x)");
+        assert_eq!(dump_source("- -x + 1\n"), "(+ (- (- x)) 1)");
+    }
+
+    #[test]
+    fn not_at_top_level() {
+        assert_eq!(dump_source("not y\n"), "(not y)");
+        assert_eq!(dump_source("not a == b\n"), "(not (== a b))");
+    }
+
+    #[test]
+    fn and_or_produce_logical_nodes() {
+        for source in ["a and b", "a or b"] {
+            let expression = Parser::parse_expression_str(source).unwrap();
+            assert!(
+                matches!(expression, Expression::LogicalExpression { .. }),
+                "{}: {:?}",
+                source,
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn arithmetic_and_equality_stay_binary() {
+        for source in ["a + b", "a == b", "a != b"] {
+            let expression = Parser::parse_expression_str(source).unwrap();
+            assert!(
+                matches!(expression, Expression::BinaryExpression(..)),
+                "{}: {:?}",
+                source,
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn postfix_chains() {
+        assert_eq!(dump_source("a.b.c()\n"), "(call (. (. a b) c))");
+        assert_eq!(dump_source("f()[0]\n"), "(subscript (call f) 0)");
+        assert_eq!(
+            dump_source("obj.method()[0].field\n"),
+            "(. (subscript (call (. obj method)) 0) field)"
+        );
+        assert_eq!(dump_source("f(1)(2)[a][b]\n"), "(subscript (subscript (call (call f 1) 2) a) b)");
+    }
+
+    #[test]
+    fn membership_and_identity_comparisons() {
+        assert_eq!(dump_source("x in items\n"), "(in x items)");
+        assert_eq!(dump_source("a is None\n"), "(is a None)");
+        assert_eq!(dump_source("x not in items\n"), "(not in x items)");
+        assert_eq!(dump_source("a is not None\n"), "(is not a None)");
+    }
+
+    #[test]
+    fn comparisons_chain_instead_of_nesting() {
+        assert_eq!(dump_source("1 < x < 10\n"), "(compare 1 < x < 10)");
+        assert_eq!(dump_source("a < b in c\n"), "(compare a < b in c)");
+        assert_eq!(
+            dump_source("a is not b not in c == d\n"),
+            "(compare a is not b not in c == d)"
+        );
+        assert_eq!(dump_source("(a < b) < c\n"), "(< (< a b) c)");
+    }
+
+    #[test]
+    fn multi_word_operators_inside_a_chain() {
+        let expression = Parser::parse_expression_str("a < b not in c").unwrap();
+        let Expression::ComparisonChain { left, comparisons } = expression else {
+            panic!("expected a comparison chain, found {:?}", expression);
+        };
+
+        let operands: Vec<&str> = std::iter::once(left.as_ref())
+            .chain(comparisons.iter().map(|(_, right)| right))
+            .map(|operand| match operand {
+                Statement::ExpressionStatement(Expression::Identifier(v)) => v.name.as_str(),
+                operand => panic!("expected a name, found {:?}", operand),
+            })
+            .collect();
+        assert_eq!(operands, vec!["a", "b", "c"]);
+        assert!(matches!(
+            comparisons.as_slice(),
+            [(Operator::LessThan, _), (Operator::NotIn, _)]
+        ));
+    }
+
+    #[test]
+    fn position_advances_past_exactly_one_statement() {
+        let tokens = tokenize("f(x)\ny\n").unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(parser.position(), 0);
+
+        parser.parse_next_statement().unwrap();
+        // f ( x ) and its newline
+        assert_eq!(parser.position(), 5);
+        assert_eq!(
+            parser.remaining(),
+            &[Token::Identifier("y".to_string()), Token::Newline, Token::EOF]
+        );
+
+        parser.parse_next_statement().unwrap();
+        assert_eq!(parser.remaining(), &[Token::EOF]);
+        assert!(parser.parse_next_statement().unwrap().is_none());
+    }
+
+    #[test]
+    fn keyword_where_a_name_is_expected() {
+        assert_eq!(
+            parse_error("obj.class\n"),
+            "Error: expected a name after '.', found 'class'"
+        );
+        assert_eq!(
+            parse_error("(x for if in xs)\n"),
+            "Error: expected a name after 'for', found 'if'"
+        );
+    }
 
-        node
+    #[test]
+    fn comment_on_the_last_line_without_newline() {
+        assert_eq!(dump_source("x\ny  # c"), "x\ny");
+        assert_eq!(dump_source("x\n# end"), "x");
     }
 }
@@ -84,185 +84,690 @@ pub enum Token {
     EOF,
 }
 
+/// Payload-free discriminant of a `Token`, for matching on what a token is
+/// regardless of the value it carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    WhiteSpace,
+    // Keywords
+    DefKeyword,
+    ReturnKeyword,
+    IfKeyword,
+    ElifKeyword,
+    ElseKeyword,
+    ForKeyword,
+    WhileKeyword,
+    BreakKeyword,
+    ContinueKeyword,
+    PassKeyword,
+    ImportKeyword,
+    FromKeyword,
+    AsKeyword,
+    TryKeyword,
+    ExceptKeyword,
+    FinallyKeyword,
+    RaiseKeyword,
+    ClassKeyword,
+    WithKeyword,
+    YieldKeyword,
+    GlobalKeyword,
+    NonlocalKeyword,
+    LambdaKeyword,
+    AsyncKeyword,
+    AwaitKeyword,
+
+    // Operators
+    Plus,               // +
+    Minus,              // -
+    Star,               // *
+    Slash,              // /
+    DoubleSlash,        // //
+    Percent,            // %
+    DoubleStar,         // **
+    Equals,             // =
+    DoubleEquals,       // ==
+    NotEquals,          // !=
+    LessThan,           // <
+    GreaterThan,        // >
+    LessThanOrEqual,    // <=
+    GreaterThanOrEqual, // >=
+    And,                // and
+    Or,                 // or
+    Not,                // not
+    In,                 // in
+    NotIn,              // not in
+    Is,                 // is
+    IsNot,              // is not
+
+    // Delimiters
+    LParen,    // (
+    RParen,    // )
+    LBrace,    // {
+    RBrace,    // }
+    LBracket,  // [
+    RBracket,  // ]
+    Comma,     // ,
+    Colon,     // :
+    Dot,       // .
+    Semicolon, // ;
+    At,        // @
+    Arrow,     // ->
+    Ellipsis,  // ...
+
+    // Literals
+    Identifier,
+    Integer,
+    Float,
+    StringLiteral,
+    BooleanLiteral,
+    NoneLiteral, // None
+
+    // Comments and Whitespace
+    Comment,
+    Newline,
+    Indent,
+    Dedent,
+
+    // End of File
+    EOF,
+}
+
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::WhiteSpace => TokenKind::WhiteSpace,
+            Token::DefKeyword => TokenKind::DefKeyword,
+            Token::ReturnKeyword => TokenKind::ReturnKeyword,
+            Token::IfKeyword => TokenKind::IfKeyword,
+            Token::ElifKeyword => TokenKind::ElifKeyword,
+            Token::ElseKeyword => TokenKind::ElseKeyword,
+            Token::ForKeyword => TokenKind::ForKeyword,
+            Token::WhileKeyword => TokenKind::WhileKeyword,
+            Token::BreakKeyword => TokenKind::BreakKeyword,
+            Token::ContinueKeyword => TokenKind::ContinueKeyword,
+            Token::PassKeyword => TokenKind::PassKeyword,
+            Token::ImportKeyword => TokenKind::ImportKeyword,
+            Token::FromKeyword => TokenKind::FromKeyword,
+            Token::AsKeyword => TokenKind::AsKeyword,
+            Token::TryKeyword => TokenKind::TryKeyword,
+            Token::ExceptKeyword => TokenKind::ExceptKeyword,
+            Token::FinallyKeyword => TokenKind::FinallyKeyword,
+            Token::RaiseKeyword => TokenKind::RaiseKeyword,
+            Token::ClassKeyword => TokenKind::ClassKeyword,
+            Token::WithKeyword => TokenKind::WithKeyword,
+            Token::YieldKeyword => TokenKind::YieldKeyword,
+            Token::GlobalKeyword => TokenKind::GlobalKeyword,
+            Token::NonlocalKeyword => TokenKind::NonlocalKeyword,
+            Token::LambdaKeyword => TokenKind::LambdaKeyword,
+            Token::AsyncKeyword => TokenKind::AsyncKeyword,
+            Token::AwaitKeyword => TokenKind::AwaitKeyword,
+            Token::Plus => TokenKind::Plus,
+            Token::Minus => TokenKind::Minus,
+            Token::Star => TokenKind::Star,
+            Token::Slash => TokenKind::Slash,
+            Token::DoubleSlash => TokenKind::DoubleSlash,
+            Token::Percent => TokenKind::Percent,
+            Token::DoubleStar => TokenKind::DoubleStar,
+            Token::Equals => TokenKind::Equals,
+            Token::DoubleEquals => TokenKind::DoubleEquals,
+            Token::NotEquals => TokenKind::NotEquals,
+            Token::LessThan => TokenKind::LessThan,
+            Token::GreaterThan => TokenKind::GreaterThan,
+            Token::LessThanOrEqual => TokenKind::LessThanOrEqual,
+            Token::GreaterThanOrEqual => TokenKind::GreaterThanOrEqual,
+            Token::And => TokenKind::And,
+            Token::Or => TokenKind::Or,
+            Token::Not => TokenKind::Not,
+            Token::In => TokenKind::In,
+            Token::NotIn => TokenKind::NotIn,
+            Token::Is => TokenKind::Is,
+            Token::IsNot => TokenKind::IsNot,
+            Token::LParen => TokenKind::LParen,
+            Token::RParen => TokenKind::RParen,
+            Token::LBrace => TokenKind::LBrace,
+            Token::RBrace => TokenKind::RBrace,
+            Token::LBracket => TokenKind::LBracket,
+            Token::RBracket => TokenKind::RBracket,
+            Token::Comma => TokenKind::Comma,
+            Token::Colon => TokenKind::Colon,
+            Token::Dot => TokenKind::Dot,
+            Token::Semicolon => TokenKind::Semicolon,
+            Token::At => TokenKind::At,
+            Token::Arrow => TokenKind::Arrow,
+            Token::Ellipsis => TokenKind::Ellipsis,
+            Token::Identifier(_) => TokenKind::Identifier,
+            Token::Integer(_) => TokenKind::Integer,
+            Token::Float(_) => TokenKind::Float,
+            Token::StringLiteral(_) => TokenKind::StringLiteral,
+            Token::BooleanLiteral(_) => TokenKind::BooleanLiteral,
+            Token::NoneLiteral => TokenKind::NoneLiteral,
+            Token::Comment(_) => TokenKind::Comment,
+            Token::Newline => TokenKind::Newline,
+            Token::Indent => TokenKind::Indent,
+            Token::Dedent => TokenKind::Dedent,
+            Token::EOF => TokenKind::EOF,
+        }
+    }
+}
+
+use std::collections::HashSet;
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
 
-pub fn tokenize(input: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
-    let mut indent_stack: Vec<usize> = [0].to_vec();
-    let mut at_line_start = true;
+/// The token as written in source, e.g. `>=` or `def`. Layout tokens have
+/// no text of their own and render as placeholders like `<newline>`.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::WhiteSpace => write!(f, " "),
+            Token::DefKeyword => write!(f, "def"),
+            Token::ReturnKeyword => write!(f, "return"),
+            Token::IfKeyword => write!(f, "if"),
+            Token::ElifKeyword => write!(f, "elif"),
+            Token::ElseKeyword => write!(f, "else"),
+            Token::ForKeyword => write!(f, "for"),
+            Token::WhileKeyword => write!(f, "while"),
+            Token::BreakKeyword => write!(f, "break"),
+            Token::ContinueKeyword => write!(f, "continue"),
+            Token::PassKeyword => write!(f, "pass"),
+            Token::ImportKeyword => write!(f, "import"),
+            Token::FromKeyword => write!(f, "from"),
+            Token::AsKeyword => write!(f, "as"),
+            Token::TryKeyword => write!(f, "try"),
+            Token::ExceptKeyword => write!(f, "except"),
+            Token::FinallyKeyword => write!(f, "finally"),
+            Token::RaiseKeyword => write!(f, "raise"),
+            Token::ClassKeyword => write!(f, "class"),
+            Token::WithKeyword => write!(f, "with"),
+            Token::YieldKeyword => write!(f, "yield"),
+            Token::GlobalKeyword => write!(f, "global"),
+            Token::NonlocalKeyword => write!(f, "nonlocal"),
+            Token::LambdaKeyword => write!(f, "lambda"),
+            Token::AsyncKeyword => write!(f, "async"),
+            Token::AwaitKeyword => write!(f, "await"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::DoubleSlash => write!(f, "//"),
+            Token::Percent => write!(f, "%"),
+            Token::DoubleStar => write!(f, "**"),
+            Token::Equals => write!(f, "="),
+            Token::DoubleEquals => write!(f, "=="),
+            Token::NotEquals => write!(f, "!="),
+            Token::LessThan => write!(f, "<"),
+            Token::GreaterThan => write!(f, ">"),
+            Token::LessThanOrEqual => write!(f, "<="),
+            Token::GreaterThanOrEqual => write!(f, ">="),
+            Token::And => write!(f, "and"),
+            Token::Or => write!(f, "or"),
+            Token::Not => write!(f, "not"),
+            Token::In => write!(f, "in"),
+            Token::NotIn => write!(f, "not in"),
+            Token::Is => write!(f, "is"),
+            Token::IsNot => write!(f, "is not"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::LBrace => write!(f, "{{"),
+            Token::RBrace => write!(f, "}}"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+            Token::Comma => write!(f, ","),
+            Token::Colon => write!(f, ":"),
+            Token::Dot => write!(f, "."),
+            Token::Semicolon => write!(f, ";"),
+            Token::At => write!(f, "@"),
+            Token::Arrow => write!(f, "->"),
+            Token::Ellipsis => write!(f, "..."),
+            Token::Identifier(v) => write!(f, "{}", v),
+            Token::Integer(v) => write!(f, "{}", v),
+            Token::Float(v) => write!(f, "{:?}", v),
+            Token::StringLiteral(v) => write!(f, "{:?}", v),
+            Token::BooleanLiteral(true) => write!(f, "True"),
+            Token::BooleanLiteral(false) => write!(f, "False"),
+            Token::NoneLiteral => write!(f, "None"),
+            Token::Comment(v) => write!(f, "#{}", v),
+            Token::Newline => write!(f, "<newline>"),
+            Token::Indent => write!(f, "<indent>"),
+            Token::Dedent => write!(f, "<dedent>"),
+            Token::EOF => write!(f, "<end of input>"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TokenizeError {
+    pub message: String,
+}
 
-    while let Some(&ch) = chars.peek() {
-        if at_line_start {
-            handle_indentation(&mut chars, &mut indent_stack, &mut tokens);
-            at_line_start = false;
+impl TokenizeError {
+    fn new(message: String) -> Self {
+        TokenizeError { message }
+    }
+}
+
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for TokenizeError {}
+
+/// Every word that tokenizes as a keyword or literal token rather than an
+/// identifier, with the token it becomes.
+const KEYWORDS: [(&str, Token); 33] = [
+    ("def", Token::DefKeyword),
+    ("return", Token::ReturnKeyword),
+    ("if", Token::IfKeyword),
+    ("else", Token::ElseKeyword),
+    ("None", Token::NoneLiteral),
+    ("True", Token::BooleanLiteral(true)),
+    ("False", Token::BooleanLiteral(false)),
+    ("elif", Token::ElifKeyword),
+    ("for", Token::ForKeyword),
+    ("while", Token::WhileKeyword),
+    ("break", Token::BreakKeyword),
+    ("continue", Token::ContinueKeyword),
+    ("pass", Token::PassKeyword),
+    ("import", Token::ImportKeyword),
+    ("from", Token::FromKeyword),
+    ("as", Token::AsKeyword),
+    ("try", Token::TryKeyword),
+    ("except", Token::ExceptKeyword),
+    ("finally", Token::FinallyKeyword),
+    ("raise", Token::RaiseKeyword),
+    ("class", Token::ClassKeyword),
+    ("with", Token::WithKeyword),
+    ("yield", Token::YieldKeyword),
+    ("global", Token::GlobalKeyword),
+    ("nonlocal", Token::NonlocalKeyword),
+    ("lambda", Token::LambdaKeyword),
+    ("async", Token::AsyncKeyword),
+    ("await", Token::AwaitKeyword),
+    ("and", Token::And),
+    ("or", Token::Or),
+    ("not", Token::Not),
+    ("in", Token::In),
+    ("is", Token::Is),
+];
+
+/// The words treated as keywords. Anything removed from the set tokenizes
+/// as a plain identifier, e.g. `async` for dialects that predate it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeywordSet {
+    words: HashSet<&'static str>,
+}
+
+impl Default for KeywordSet {
+    fn default() -> Self {
+        KeywordSet {
+            words: KEYWORDS.iter().map(|(word, _)| *word).collect(),
         }
+    }
+}
 
-        match ch {
-            ' ' | '\t' => {
-                consume_whitespace(&mut chars);
-                // tokens.push(Token::WhiteSpace);
-            }
+impl KeywordSet {
+    pub fn remove(&mut self, word: &str) {
+        self.words.remove(word);
+    }
 
-            '\n' | '\r' => {
-                chars.next();
-                tokens.push(Token::Newline);
-                at_line_start = true;
+    /// Reserves `word` again. Only words with a keyword token can be
+    /// reserved, so for any other word this does nothing and returns `false`.
+    pub fn insert(&mut self, word: &str) -> bool {
+        match KEYWORDS.iter().find(|(keyword, _)| *keyword == word) {
+            Some((keyword, _)) => {
+                self.words.insert(keyword);
+                true
             }
+            None => false,
+        }
+    }
 
-            '(' => {
-                chars.next();
-                tokens.push(Token::LParen);
-            }
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(word)
+    }
+}
 
-            ')' => {
-                chars.next();
-                tokens.push(Token::RParen);
-            }
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TokenizerOptions {
+    pub reserved: KeywordSet,
+}
 
-            '{' => {
-                chars.next();
-                tokens.push(Token::LBrace);
-            }
+/// Tokenizer state carried from one line to the next, so an editor can keep
+/// a snapshot per line and re-tokenize only the line that changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tokenizer {
+    indent_stack: Vec<usize>,
+    // Newlines inside brackets continue the logical line
+    bracket_depth: usize,
+    // Whether the input so far ends in the middle of a logical line
+    line_open: bool,
+    // A triple-quoted string still waiting for its closing quotes: the quote
+    // character and the text so far
+    open_string: Option<(char, String)>,
+    options: TokenizerOptions,
+}
 
-            '}' => {
-                chars.next();
-                tokens.push(Token::RBrace);
-            }
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            '[' => {
-                chars.next();
-                tokens.push(Token::LBracket);
-            }
+impl Tokenizer {
+    pub fn new() -> Self {
+        Self::with_options(TokenizerOptions::default())
+    }
 
-            ']' => {
-                chars.next();
-                tokens.push(Token::RBracket);
+    pub fn with_options(options: TokenizerOptions) -> Self {
+        Tokenizer {
+            indent_stack: [0].to_vec(),
+            bracket_depth: 0,
+            line_open: false,
+            open_string: None,
+            options,
+        }
+    }
+
+    /// Tokenizes the next line (or several) of the input, continuing from the
+    /// state left by earlier calls. A triple-quoted string may run on into
+    /// later lines, and is emitted by the call that sees it close. The
+    /// closing dedents and EOF are only emitted by `finish`.
+    pub fn tokenize_line(&mut self, input: &str) -> Result<Vec<Token>, TokenizeError> {
+        let mut tokens = Vec::new();
+        let mut chars = input.chars().peekable();
+        let mut at_line_start = self.bracket_depth == 0 && self.open_string.is_none();
+
+        while let Some(&ch) = chars.peek() {
+            if let Some((quote, mut literal)) = self.open_string.take() {
+                if consume_triple_quoted_string(&mut chars, quote, &mut literal) {
+                    tokens.push(Token::StringLiteral(literal));
+                } else {
+                    self.open_string = Some((quote, literal));
+                }
+                continue;
             }
-            '+' => {
-                chars.next();
-                tokens.push(Token::Plus);
+
+            if at_line_start {
+                handle_indentation(&mut chars, &mut self.indent_stack, &mut tokens)?;
+                at_line_start = false;
             }
 
-            '-' => {
-                chars.next();
-                if chars.peek() == Some(&'>') {
+            match ch {
+                ' ' | '\t' => {
+                    consume_whitespace(&mut chars);
+                    // tokens.push(Token::WhiteSpace);
+                }
+
+                '\n' | '\r' => {
                     chars.next();
-                    tokens.push(Token::Arrow);
-                } else {
-                    tokens.push(Token::Minus);
+                    if ch == '\r' && chars.peek() == Some(&'\n') {
+                        chars.next();
+                    }
+
+                    if self.bracket_depth == 0 {
+                        // Emit one terminator per logical line, none for blank ones
+                        if ends_logical_line(&tokens) {
+                            tokens.push(Token::Newline);
+                        }
+                        at_line_start = true;
+                    }
                 }
-            }
 
-            '*' => {
-                chars.next();
-                if chars.peek() == Some(&'*') {
+                '(' => {
                     chars.next();
-                    tokens.push(Token::DoubleStar);
-                } else {
-                    tokens.push(Token::Star);
+                    self.bracket_depth += 1;
+                    tokens.push(Token::LParen);
                 }
-            }
 
-            '/' => {
-                chars.next();
-                if chars.peek() == Some(&'/') {
+                ')' => {
                     chars.next();
-                    tokens.push(Token::DoubleSlash);
-                } else {
-                    tokens.push(Token::Slash);
+                    self.bracket_depth = self.bracket_depth.saturating_sub(1);
+                    tokens.push(Token::RParen);
                 }
-            }
 
-            '=' => {
-                chars.next();
-                if chars.peek() == Some(&'=') {
+                '{' => {
                     chars.next();
-                    tokens.push(Token::DoubleEquals);
-                } else {
-                    tokens.push(Token::Equals);
+                    self.bracket_depth += 1;
+                    tokens.push(Token::LBrace);
                 }
-            }
 
-            '!' => {
-                chars.next();
-                if chars.peek() == Some(&'=') {
+                '}' => {
                     chars.next();
-                    tokens.push(Token::NotEquals);
+                    self.bracket_depth = self.bracket_depth.saturating_sub(1);
+                    tokens.push(Token::RBrace);
                 }
-            }
 
-            '<' => {
-                chars.next();
-                if chars.peek() == Some(&'=') {
+                '[' => {
                     chars.next();
-                    tokens.push(Token::LessThanOrEqual)
-                } else {
-                    tokens.push(Token::LessThan);
+                    self.bracket_depth += 1;
+                    tokens.push(Token::LBracket);
                 }
-            }
 
-            '>' => {
-                chars.next();
-                if chars.peek() == Some(&'=') {
+                ']' => {
                     chars.next();
-                    tokens.push(Token::GreaterThanOrEqual)
-                } else {
-                    tokens.push(Token::GreaterThan);
+                    self.bracket_depth = self.bracket_depth.saturating_sub(1);
+                    tokens.push(Token::RBracket);
+                }
+                '+' => {
+                    chars.next();
+                    tokens.push(Token::Plus);
                 }
-            }
 
-            '\'' | '"' => {
-                tokens.push(consume_string_literal(&mut chars));
-            }
+                '-' => {
+                    chars.next();
+                    if chars.peek() == Some(&'>') {
+                        chars.next();
+                        tokens.push(Token::Arrow);
+                    } else {
+                        tokens.push(Token::Minus);
+                    }
+                }
 
-            '#' => {
-                tokens.push(consume_comment(&mut chars));
-            }
+                '*' => {
+                    chars.next();
+                    if chars.peek() == Some(&'*') {
+                        chars.next();
+                        tokens.push(Token::DoubleStar);
+                    } else {
+                        tokens.push(Token::Star);
+                    }
+                }
 
-            ';' => {
-                chars.next();
-                tokens.push(Token::Semicolon);
-            }
+                '/' => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        tokens.push(Token::DoubleSlash);
+                    } else {
+                        tokens.push(Token::Slash);
+                    }
+                }
 
-            ':' => {
-                chars.next();
-                tokens.push(Token::Colon);
-            }
+                '=' => {
+                    chars.next();
+                    if chars.peek() == Some(&'=') {
+                        chars.next();
+                        tokens.push(Token::DoubleEquals);
+                    } else {
+                        tokens.push(Token::Equals);
+                    }
+                }
 
-            ',' => {
-                chars.next();
-                tokens.push(Token::Comma);
-            }
+                '!' => {
+                    chars.next();
+                    if chars.peek() == Some(&'=') {
+                        chars.next();
+                        tokens.push(Token::NotEquals);
+                    }
+                }
 
-            '%' => {
-                chars.next();
-                tokens.push(Token::Percent);
-            }
+                '<' => {
+                    chars.next();
+                    if chars.peek() == Some(&'=') {
+                        chars.next();
+                        tokens.push(Token::LessThanOrEqual)
+                    } else if chars.peek() == Some(&'>') {
+                        return Err(TokenizeError::new(
+                            "Error: '<>' is not a valid operator, use '!=' instead".to_string(),
+                        ));
+                    } else {
+                        tokens.push(Token::LessThan);
+                    }
+                }
 
-            '0'..='9' => {
-                tokens.push(consume_number(&mut chars));
-            }
+                '>' => {
+                    chars.next();
+                    if chars.peek() == Some(&'=') {
+                        chars.next();
+                        tokens.push(Token::GreaterThanOrEqual)
+                    } else {
+                        tokens.push(Token::GreaterThan);
+                    }
+                }
 
-            _ if ch.is_alphabetic() || ch == '_' => {
-                tokens.push(consume_identifier_or_keyword(&mut chars));
-            }
+                '\'' | '"' => {
+                    // Triple quoted strings may span lines and contain single quotes
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    if lookahead.next() == Some(ch) && lookahead.next() == Some(ch) {
+                        chars.next();
+                        chars.next();
+                        chars.next();
+                        self.open_string = Some((ch, String::new()));
+                    } else {
+                        tokens.push(consume_string_literal(&mut chars)?);
+                    }
+                }
 
-            _ => {
-                chars.next();
+                '#' => {
+                    tokens.push(consume_comment(&mut chars));
+                }
+
+                ';' => {
+                    chars.next();
+                    tokens.push(Token::Semicolon);
+                }
+
+                ':' => {
+                    chars.next();
+                    tokens.push(Token::Colon);
+                }
+
+                ',' => {
+                    chars.next();
+                    tokens.push(Token::Comma);
+                }
+
+                '%' => {
+                    chars.next();
+                    tokens.push(Token::Percent);
+                }
+
+                '@' => {
+                    chars.next();
+                    tokens.push(Token::At);
+                }
+
+                '.' => {
+                    chars.next();
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some('.') && lookahead.next() == Some('.') {
+                        chars.next();
+                        chars.next();
+                        tokens.push(Token::Ellipsis);
+                    } else {
+                        tokens.push(Token::Dot);
+                    }
+                }
+
+                '0'..='9' => {
+                    tokens.push(consume_number(&mut chars)?);
+                }
+
+                _ if ch.is_alphabetic() || ch == '_' => {
+                    let token = consume_identifier_or_keyword(&mut chars, &self.options.reserved);
+
+                    // Fold the two word operators `not in` and `is not` into one token
+                    match (tokens.last(), &token) {
+                        (Some(Token::Not), Token::In) => {
+                            tokens.pop();
+                            tokens.push(Token::NotIn);
+                        }
+                        (Some(Token::Is), Token::Not) => {
+                            tokens.pop();
+                            tokens.push(Token::IsNot);
+                        }
+                        _ => tokens.push(token),
+                    }
+                }
+
+                _ => {
+                    chars.next();
+                }
             }
         }
+
+        let last = tokens
+            .iter()
+            .rev()
+            .find(|token| !matches!(token, Token::Comment(_)));
+        if let Some(last) = last {
+            self.line_open = *last != Token::Newline;
+        }
+
+        Ok(tokens)
+    }
+
+    /// Whether the input so far stops inside brackets or a triple-quoted
+    /// string, so its last logical line carries on into the next one.
+    pub fn line_continues(&self) -> bool {
+        self.bracket_depth > 0 || self.open_string.is_some()
+    }
+
+    /// Closes the last logical line and any blocks still open, then ends the
+    /// stream with EOF. Fails if a triple-quoted string was never closed.
+    pub fn finish(&mut self) -> Result<Vec<Token>, TokenizeError> {
+        if self.open_string.is_some() {
+            return Err(TokenizeError::new(
+                "Error: unterminated string literal".to_string(),
+            ));
+        }
+
+        let mut tokens = Vec::new();
+
+        if self.line_open {
+            tokens.push(Token::Newline);
+            self.line_open = false;
+        }
+        while self.indent_stack.len() > 1 {
+            self.indent_stack.pop();
+            tokens.push(Token::Dedent);
+        }
+
+        tokens.push(Token::EOF);
+        Ok(tokens)
     }
-    tokens.push(Token::EOF);
-    return tokens;
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, TokenizeError> {
+    tokenize_with(input, TokenizerOptions::default())
+}
+
+pub fn tokenize_with(input: &str, options: TokenizerOptions) -> Result<Vec<Token>, TokenizeError> {
+    let mut tokenizer = Tokenizer::with_options(options);
+    let mut tokens = tokenizer.tokenize_line(input)?;
+    tokens.extend(tokenizer.finish()?);
+    Ok(tokens)
 }
 
 fn handle_indentation(
     chars: &mut Peekable<Chars>,
     indent_stack: &mut Vec<usize>,
     tokens: &mut Vec<Token>,
-) {
+) -> Result<(), TokenizeError> {
     let mut indent_level = 0;
 
     // Count spaces or tabs for indentation level
@@ -277,32 +782,96 @@ fn handle_indentation(
         chars.next();
     }
 
+    // Blank and comment-only lines don't affect indentation
+    if let None | Some('\n') | Some('\r') | Some('#') = chars.peek() {
+        return Ok(());
+    }
+
     // Check the change in indentation
-    let current_level = *indent_stack.last().unwrap();
+    let current_level = indent_stack.last().copied().unwrap_or(0);
     if indent_level > current_level {
         indent_stack.push(indent_level);
         tokens.push(Token::Indent);
     } else if indent_level < current_level {
-        while indent_stack.last().unwrap() > &indent_level {
+        while let Some(&level) = indent_stack.last() {
+            if level <= indent_level {
+                break;
+            }
             indent_stack.pop();
             tokens.push(Token::Dedent);
         }
+
+        if indent_stack.last().copied().unwrap_or(0) != indent_level {
+            return Err(TokenizeError::new(
+                "Error: unindent does not match any outer indentation level".to_string(),
+            ));
+        }
     }
+
+    Ok(())
+}
+
+/// Whether a newline at this point ends a logical line, i.e. something other
+/// than comments has been emitted since the previous terminator.
+fn ends_logical_line(tokens: &[Token]) -> bool {
+    let last = tokens
+        .iter()
+        .rev()
+        .find(|token| !matches!(token, Token::Comment(_)));
+
+    !matches!(last, None | Some(Token::Newline))
 }
 
-fn consume_string_literal(chars: &mut Peekable<Chars>) -> Token {
-    let quote = chars.next().unwrap(); // Consume the opening quote
+fn consume_string_literal(chars: &mut Peekable<Chars>) -> Result<Token, TokenizeError> {
+    // Consume the opening quote
+    let quote = match chars.next() {
+        Some(quote) => quote,
+        None => {
+            return Err(TokenizeError::new(
+                "Error: expected a string literal".to_string(),
+            ))
+        }
+    };
     let mut literal = String::new();
 
+    // Single quoted strings must be closed on the line they start
     while let Some(&ch) = chars.peek() {
+        if ch == '\n' || ch == '\r' {
+            break;
+        }
         chars.next();
         if ch == quote {
-            break; // Closing quote found
+            return Ok(Token::StringLiteral(literal)); // Closing quote found
+        }
+        literal.push(ch);
+    }
+
+    Err(TokenizeError::new(
+        "Error: unterminated string literal".to_string(),
+    ))
+}
+
+/// Consumes the body of a triple-quoted string whose opening quotes are
+/// already consumed, appending it to `literal`. Returns whether the closing
+/// quotes were found before the input ran out.
+fn consume_triple_quoted_string(
+    chars: &mut Peekable<Chars>,
+    quote: char,
+    literal: &mut String,
+) -> bool {
+    while let Some(ch) = chars.next() {
+        if ch == quote {
+            let mut lookahead = chars.clone();
+            if lookahead.next() == Some(quote) && lookahead.next() == Some(quote) {
+                chars.next();
+                chars.next();
+                return true;
+            }
         }
         literal.push(ch);
     }
 
-    Token::StringLiteral(literal)
+    false
 }
 
 fn consume_comment(chars: &mut Peekable<Chars>) -> Token {
@@ -310,7 +879,7 @@ fn consume_comment(chars: &mut Peekable<Chars>) -> Token {
     let mut comment = String::new();
 
     while let Some(&ch) = chars.peek() {
-        if ch == '\n' {
+        if ch == '\n' || ch == '\r' {
             break; // End of comment
         }
         chars.next();
@@ -320,11 +889,11 @@ fn consume_comment(chars: &mut Peekable<Chars>) -> Token {
     Token::Comment(comment)
 }
 
-fn consume_number(chars: &mut Peekable<Chars>) -> Token {
+fn consume_number(chars: &mut Peekable<Chars>) -> Result<Token, TokenizeError> {
     let mut number = String::new();
 
     while let Some(&ch) = chars.peek() {
-        if !ch.is_numeric() && ch != '.' {
+        if !ch.is_ascii_digit() && ch != '.' {
             break;
         }
         chars.next();
@@ -332,9 +901,22 @@ fn consume_number(chars: &mut Peekable<Chars>) -> Token {
     }
 
     if number.contains('.') {
-        Token::Float(number.parse().unwrap())
+        match number.parse() {
+            Ok(v) => Ok(Token::Float(v)),
+            Err(_) => Err(TokenizeError::new(format!(
+                "Error: invalid number literal {}",
+                number
+            ))),
+        }
     } else {
-        Token::Integer(number.parse().unwrap())
+        match number.parse() {
+            Ok(v) => Ok(Token::Integer(v)),
+            Err(_) => Err(TokenizeError::new(format!(
+                "Error: integer literal {} is too large (maximum is {})",
+                number,
+                i64::MAX
+            ))),
+        }
     }
 }
 
@@ -347,7 +929,7 @@ fn consume_whitespace(chars: &mut Peekable<Chars>) {
     }
 }
 
-fn consume_identifier_or_keyword(chars: &mut Peekable<Chars>) -> Token {
+fn consume_identifier_or_keyword(chars: &mut Peekable<Chars>, reserved: &KeywordSet) -> Token {
     let mut identifier = String::new();
 
     while let Some(&ch) = chars.peek() {
@@ -358,40 +940,332 @@ fn consume_identifier_or_keyword(chars: &mut Peekable<Chars>) -> Token {
         identifier.push(ch);
     }
 
-    match identifier.as_str() {
-        "def" => Token::DefKeyword,
-        "return" => Token::ReturnKeyword,
-        "if" => Token::IfKeyword,
-        "else" => Token::ElseKeyword,
-        "None" => Token::NoneLiteral,
-        "True" => Token::BooleanLiteral(true),
-        "False" => Token::BooleanLiteral(false),
-        "elif" => Token::ElifKeyword,
-        "for" => Token::ForKeyword,
-        "while" => Token::WhileKeyword,
-        "break" => Token::BreakKeyword,
-        "continue" => Token::ContinueKeyword,
-        "pass" => Token::PassKeyword,
-        "import" => Token::ImportKeyword,
-        "from" => Token::FromKeyword,
-        "as" => Token::AsKeyword,
-        "try" => Token::TryKeyword,
-        "except" => Token::ExceptKeyword,
-        "finally" => Token::FinallyKeyword,
-        "raise" => Token::RaiseKeyword,
-        "class" => Token::ClassKeyword,
-        "with" => Token::WithKeyword,
-        "yield" => Token::YieldKeyword,
-        "global" => Token::GlobalKeyword,
-        "lambda" => Token::LambdaKeyword,
-        "async" => Token::AsyncKeyword,
-        "await" => Token::AwaitKeyword,
-        "and" => Token::And,
-        "or" => Token::Or,
-        "not" => Token::Not,
-        "in" => Token::In,
-        "is" => Token::Is,
-        "is not" => Token::IsNot,
-        _ => Token::Identifier(identifier),
+    if !reserved.contains(&identifier) {
+        return Token::Identifier(identifier);
+    }
+
+    match KEYWORDS.iter().find(|(word, _)| *word == identifier) {
+        Some((_, token)) => token.clone(),
+        None => Token::Identifier(identifier),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unterminated_triple_quoted_string_is_an_error() {
+        let err = tokenize("x = \"\"\"abc").unwrap_err();
+        assert_eq!(err.message, "Error: unterminated string literal");
+    }
+
+    #[test]
+    fn triple_quoted_string_spans_lines() {
+        let tokens = tokenize("\"\"\"first\nsecond\"\"\"\n").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::StringLiteral("first\nsecond".to_string()),
+                Token::Newline,
+                Token::EOF,
+            ]
+        );
+    }
+
+    /// Builds a string of characters the tokenizer treats specially, using
+    /// xorshift so a failing input can be reproduced from the seed.
+    fn random_source(seed: &mut u64, len: usize) -> String {
+        const ALPHABET: &[char] = &[
+            'a', 'x', '_', '0', '9', '.', 'e', ' ', ' ', '\t', '\n', '\r', '#', '\'', '"', '(',
+            ')', '[', ']', '{', '}', ',', ':', ';', '+', '-', '*', '/', '%', '@', '<', '>', '=',
+            '!', '\\', 'é', '€',
+        ];
+
+        (0..len)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 7;
+                *seed ^= *seed << 17;
+                ALPHABET[(*seed % ALPHABET.len() as u64) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_never_panics_on_random_input() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15;
+        for len in 0..2000 {
+            let source = random_source(&mut seed, len % 64);
+            let _ = tokenize(&source);
+        }
+    }
+
+    #[test]
+    fn tokenize_never_panics_on_tricky_input() {
+        for source in [
+            "",
+            "\"",
+            "\"\"\"",
+            "'''\n",
+            "1e",
+            "1.2.3",
+            "99999999999999999999999",
+            "\t\n  x",
+            "  x\n y",
+            "(\n",
+            ")",
+            "\r",
+            "#",
+            "x = \\",
+            "not",
+            "is not",
+            "<>",
+        ] {
+            let _ = tokenize(source);
+        }
+    }
+
+    #[test]
+    fn tokens_display_as_source_text() {
+        let tokens = tokenize("if x not in {'a': 1.5}: # c\n").unwrap();
+        let text: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "if",
+                "x",
+                "not in",
+                "{",
+                "\"a\"",
+                ":",
+                "1.5",
+                "}",
+                ":",
+                "# c",
+                "<newline>",
+                "<end of input>",
+            ]
+        );
+    }
+
+    #[test]
+    fn unreserved_keyword_tokenizes_as_identifier() {
+        let mut options = TokenizerOptions::default();
+        options.reserved.remove("async");
+        let tokens = tokenize_with("async await", options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("async".to_string()),
+                Token::AwaitKeyword,
+                Token::Newline,
+                Token::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn reserving_a_keyword_again() {
+        let mut options = TokenizerOptions::default();
+        options.reserved.remove("True");
+        assert!(options.reserved.insert("True"));
+        assert!(!options.reserved.insert("print"));
+        let tokens = tokenize_with("True", options).unwrap();
+        assert_eq!(tokens[0], Token::BooleanLiteral(true));
+    }
+
+    /// Tokenizes `lines` one at a time, keeping the state before each line.
+    fn tokenize_by_line(lines: &[&str]) -> (Vec<Tokenizer>, Vec<Vec<Token>>) {
+        let mut tokenizer = Tokenizer::new();
+        let mut snapshots = Vec::new();
+        let mut tokens = Vec::new();
+        for line in lines {
+            snapshots.push(tokenizer.clone());
+            tokens.push(tokenizer.tokenize_line(line).unwrap());
+        }
+        snapshots.push(tokenizer);
+        (snapshots, tokens)
+    }
+
+    #[test]
+    fn retokenizing_an_edited_line_matches_a_full_tokenize() {
+        let mut lines = vec!["x = f(1,\n", "      2)\n", "if x:\n", "    y\n", "z\n"];
+        let (snapshots, mut tokens) = tokenize_by_line(&lines);
+
+        // Indent line 3 further and re-tokenize from the state before it
+        lines[3] = "        y + 1  # deeper\n";
+        let mut tokenizer = snapshots[3].clone();
+        tokens.truncate(3);
+        for line in &lines[3..] {
+            tokens.push(tokenizer.tokenize_line(line).unwrap());
+        }
+
+        let mut incremental: Vec<Token> = tokens.concat();
+        incremental.extend(tokenizer.finish().unwrap());
+        assert_eq!(incremental, tokenize(&lines.concat()).unwrap());
+    }
+
+    #[test]
+    fn line_by_line_matches_whole_input_on_random_programs() {
+        const LINES: &[&str] = &[
+            "x = 1\n",
+            "    y\n",
+            "  z\n",
+            "if a: # c\n",
+            "f(a,\n",
+            "  b)\n",
+            "[1,\n",
+            "2]\n",
+            "\n",
+            "   \n",
+            "# only a comment\n",
+            "s = 'q'\r\n",
+            "t = '''u'''\n",
+            "d = '''first\n",
+            "last'''\n",
+            "\tw\n",
+        ];
+
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..400 {
+            let lines: Vec<&str> = (0..8)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    LINES[(seed % LINES.len() as u64) as usize]
+                })
+                .collect();
+
+            let whole = tokenize(&lines.concat());
+            let mut tokenizer = Tokenizer::new();
+            let by_line = lines
+                .iter()
+                .map(|line| tokenizer.tokenize_line(line))
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|tokens| {
+                    let mut tokens = tokens.concat();
+                    tokens.extend(tokenizer.finish()?);
+                    Ok(tokens)
+                });
+
+            match (whole, by_line) {
+                (Ok(whole), Ok(by_line)) => assert_eq!(whole, by_line, "{:?}", lines),
+                (Err(whole), Err(by_line)) => assert_eq!(whole.message, by_line.message),
+                (whole, by_line) => panic!("{:?}: {:?} vs {:?}", lines, whole, by_line),
+            }
+        }
+    }
+
+    #[test]
+    fn docstring_carries_over_to_the_next_line() {
+        let lines = ["def f():\n", "    s = '''a\n", "  b'''\n", "    x\n"];
+        let (_, tokens) = tokenize_by_line(&lines);
+        assert_eq!(tokens[1].last(), Some(&Token::Equals));
+        assert_eq!(tokens[2][0], Token::StringLiteral("a\n  b".to_string()));
+
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.tokenize_line("s = '''a\n").unwrap();
+        let err = tokenizer.finish().unwrap_err();
+        assert_eq!(err.message, "Error: unterminated string literal");
+    }
+
+    #[test]
+    fn nonlocal_is_a_keyword() {
+        assert_eq!(
+            tokenize("nonlocal global").unwrap()[..2],
+            [Token::NonlocalKeyword, Token::GlobalKeyword]
+        );
+    }
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source)
+            .unwrap()
+            .iter()
+            .map(|token| token.kind())
+            .collect()
+    }
+
+    #[test]
+    fn newlines_inside_brackets_continue_the_line() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("f(a,\n  b)\nx\n"),
+            vec![
+                Identifier, LParen, Identifier, Comma, Identifier, RParen, Newline, Identifier,
+                Newline, EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_inside_brackets_continue_the_line() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("f(a,  # first\n  b)\n"),
+            vec![Identifier, LParen, Identifier, Comma, Comment, Identifier, RParen, Newline, EOF]
+        );
+    }
+
+    #[test]
+    fn blank_and_comment_lines_add_no_terminators() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("x\n\n   \n# note\n    # indented note\ny\n"),
+            vec![Identifier, Newline, Comment, Comment, Identifier, Newline, EOF]
+        );
+    }
+
+    #[test]
+    fn crlf_ends_a_line_once() {
+        assert_eq!(kinds("x\r\ny\r\n"), kinds("x\ny\n"));
+    }
+
+    #[test]
+    fn comment_before_crlf_has_no_trailing_carriage_return() {
+        let tokens = tokenize("x  # comment\r\ny\r\n").unwrap();
+        assert_eq!(tokens[1], Token::Comment(" comment".into()));
+    }
+
+    #[test]
+    fn missing_final_newline_still_ends_the_statement() {
+        assert_eq!(kinds("x"), kinds("x\n"));
+    }
+
+    #[test]
+    fn trailing_comment_at_eof_without_newline() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("x\n    y  # c"),
+            vec![Identifier, Newline, Indent, Identifier, Comment, Newline, Dedent, EOF]
+        );
+        assert_eq!(kinds("x\n# end"), vec![Identifier, Newline, Comment, EOF]);
+    }
+
+    #[test]
+    fn python2_not_equal_suggests_bang_equals() {
+        let err = tokenize("if a <> b: pass\n").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Error: '<>' is not a valid operator, use '!=' instead"
+        );
+        assert_eq!(kinds("a < >b"), kinds("a < > b"));
+    }
+
+    #[test]
+    fn integer_overflow_is_a_tokenize_error() {
+        let err = tokenize("99999999999999999999999").unwrap_err();
+        assert_eq!(
+            err.message,
+            format!(
+                "Error: integer literal 99999999999999999999999 is too large (maximum is {})",
+                i64::MAX
+            )
+        );
+        assert_eq!(
+            tokenize(&i64::MAX.to_string()).unwrap()[0],
+            Token::Integer(i64::MAX)
+        );
     }
 }
@@ -0,0 +1,5 @@
+pub mod language {
+    pub mod ast;
+    pub mod parser;
+    pub mod tokenizer;
+}
@@ -1,14 +1,78 @@
-use language::{parser::Parser, tokenizer::tokenize};
-mod language {
-    pub mod parser;
-    pub mod tokenizer;
-}
+use std::{env, fs, process};
+
+use pydb::language::{parser::Parser, tokenizer::tokenize};
+
+const USAGE: &str = "Usage: pydb <file> [--tokens | --check]";
 
 fn main() {
-    let s = r#"a ** 3"#;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let dump_tokens = args.iter().any(|arg| arg == "--tokens");
+    let check_only = args.iter().any(|arg| arg == "--check");
+
+    if let Some(flag) = args
+        .iter()
+        .find(|arg| arg.starts_with("--") && *arg != "--tokens" && *arg != "--check")
+    {
+        eprintln!("Error: unknown option {}", flag);
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let path = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let s = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Error: could not read {}: {}", path, err);
+            process::exit(2);
+        }
+    };
+
+    let tokens = match tokenize(&s) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    };
+
+    // Only run the lexer, handy for debugging it in isolation
+    if dump_tokens {
+        for token in &tokens {
+            println!("{:?}", token);
+        }
+        return;
+    }
+
+    // Only validate the syntax, reporting every error and staying silent when
+    // it is fine
+    if check_only {
+        let mut failed = false;
+        Parser::parse_with(&s, |err| {
+            eprintln!("{}: {}", path, err);
+            failed = true;
+            true
+        });
+        if failed {
+            process::exit(1);
+        }
+        return;
+    }
 
-    let tokens = tokenize(s);
     let mut parser = Parser::new(&tokens);
+
     println!("Tokens :: {:?}\n", tokens);
-    println!("{:#?}", parser.parse());
+    match parser.parse() {
+        Ok(program) => println!("{:#?}", program),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
 }
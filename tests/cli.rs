@@ -0,0 +1,117 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Writes `source` to a file of its own in the temp directory.
+fn write_source(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("pydb-{}-{}.py", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn run_pydb(path: &Path, flag: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pydb"))
+        .arg(path)
+        .arg(flag)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn tokens_flag_dumps_one_token_per_line() {
+    let path = write_source("tokens", "print(x + 1)\n");
+    let output = run_pydb(&path, "--tokens");
+    fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<&str>>(),
+        vec![
+            "Identifier(\"print\")",
+            "LParen",
+            "Identifier(\"x\")",
+            "Plus",
+            "Integer(1)",
+            "RParen",
+            "Newline",
+            "EOF",
+        ]
+    );
+}
+
+#[test]
+fn check_flag_is_silent_and_succeeds_on_valid_input() {
+    let path = write_source("check-valid", "f(x)\nraise E from err\n");
+    let output = run_pydb(&path, "--check");
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn check_flag_fails_with_a_diagnostic_on_invalid_input() {
+    let path = write_source("check-invalid", "f(x\n");
+    let output = run_pydb(&path, "--check");
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr,
+        format!("{}: Error: '(' was never closed in `f ( x`\n", path.display())
+    );
+}
+
+#[test]
+fn check_flag_reports_every_error() {
+    let path = write_source("check-several", "a +\nok\nb c\n");
+    let output = run_pydb(&path, "--check");
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stderr);
+    assert!(lines[0].ends_with("Error: unexpected <newline>"));
+    assert!(lines[1].ends_with("Error: expected end of statement, found 'c'"));
+}
+
+#[test]
+fn check_flag_fails_on_tokenize_errors() {
+    let path = write_source("check-tokenize", "a <> b\n");
+    let output = run_pydb(&path, "--check");
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn parse_errors_fail_without_a_flag() {
+    let path = write_source("default-invalid", "a +\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_pydb"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with(&format!("{}: ", path.display())), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+}
+
+#[test]
+fn unknown_flags_are_rejected() {
+    let path = write_source("unknown-flag", "x\n");
+    let output = run_pydb(&path, "--bogus");
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown option --bogus"), "{}", stderr);
+    assert!(stderr.contains("Usage: pydb"), "{}", stderr);
+}